@@ -2,7 +2,10 @@ use std::sync::OnceLock;
 
 pub use tracing::*;
 pub use tracing_subscriber;
-use tracing_subscriber::{filter::Directive, prelude::*, registry::LookupSpan, EnvFilter, Layer};
+use tracing_subscriber::{
+    filter::Directive, fmt::writer::BoxMakeWriter, prelude::*, registry::LookupSpan, EnvFilter,
+    Layer,
+};
 
 /// threadpool to execute all tasks on
 static RAYON_PRICING_THREADPOOL: OnceLock<rayon::ThreadPool> = OnceLock::new();
@@ -12,21 +15,23 @@ pub type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
 
 /// Initializes a new [Subscriber] based on the given layers.
 /// Initializes a new [rayon::ThreadPool]
-pub fn init_all(directive: Directive) {
+///
+/// The logs go to stderr with `log_to_stderr`, e.g. when the values are written to stdout.
+pub fn init_all(directive: Directive, log_to_stderr: bool) {
     tracing_subscriber::registry()
-        .with(stdout(directive))
+        .with(fmt_layer(directive, log_to_stderr))
         .init();
     init_threadpool();
 }
 
-/// Builds a new tracing layer that writes to stdout.
+/// Builds a new tracing layer that writes to stdout, or stderr with `to_stderr`.
 ///
 /// The events are filtered by `default_directive`, unless overridden by
 /// `RUST_LOG`.
 ///
 /// Colors can be disabled with `RUST_LOG_STYLE=never`, and event targets can be
 /// displayed with `RUST_LOG_TARGET=1`.
-fn stdout<S>(default_directive: Directive) -> BoxedLayer<S>
+fn fmt_layer<S>(default_directive: Directive, to_stderr: bool) -> BoxedLayer<S>
 where
     S: Subscriber,
     for<'a> S: LookupSpan<'a>,
@@ -39,7 +44,14 @@ where
         .with_default_directive(default_directive)
         .from_env_lossy();
 
+    let writer = if to_stderr {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+
     tracing_subscriber::fmt::layer()
+        .with_writer(writer)
         .with_ansi(true)
         .with_target(with_target)
         .with_filter(filter)
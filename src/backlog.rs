@@ -0,0 +1,135 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use tokio::sync::mpsc::{error::SendError, unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    pools::types::{PoolData, PoolUpdate},
+    progress::{RowCounts, WriteCounts},
+};
+
+/// the values sent to the writer that have not been written yet, the handler stops launching
+/// blocks while either budget is exceeded
+#[derive(Debug)]
+pub struct WriteBacklog {
+    rows: AtomicUsize,
+    bytes: AtomicUsize,
+    pub max_rows: usize,
+    pub max_bytes: usize,
+}
+
+impl WriteBacklog {
+    pub fn new(max_rows: usize, max_bytes: usize) -> Self {
+        Self {
+            rows: AtomicUsize::new(0),
+            bytes: AtomicUsize::new(0),
+            max_rows,
+            max_bytes,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.rows() >= self.max_rows || self.bytes() >= self.max_bytes
+    }
+
+    pub fn add(&self, values: &[PoolData]) {
+        self.rows.fetch_add(values.len(), Ordering::Relaxed);
+        self.bytes.fetch_add(values_size(values), Ordering::Relaxed);
+    }
+
+    pub fn remove(&self, values: &[PoolData]) {
+        self.rows.fetch_sub(values.len(), Ordering::Relaxed);
+        self.bytes.fetch_sub(values_size(values), Ordering::Relaxed);
+    }
+}
+
+fn values_size(values: &[PoolData]) -> usize {
+    values.iter().map(PoolData::approx_size).sum()
+}
+
+/// sends the fetched values to the writer, counting them against the backlog
+#[derive(Debug, Clone)]
+pub struct PoolDataSender {
+    tx: UnboundedSender<PoolUpdate>,
+    pub backlog: Arc<WriteBacklog>,
+    /// every value produced so far, for progress reporting
+    pub rows: Arc<RowCounts>,
+    /// the writer's retries and give-ups, for progress reporting
+    pub writes: Arc<WriteCounts>,
+}
+
+impl PoolDataSender {
+    pub fn send(&self, update: PoolUpdate) -> Result<(), SendError<PoolUpdate>> {
+        if let PoolUpdate::Block(block) = &update {
+            self.backlog.add(&block.data);
+            self.rows.add(&block.data);
+        }
+
+        self.tx.send(update).map_err(|e| {
+            if let PoolUpdate::Block(block) = &e.0 {
+                self.backlog.remove(&block.data);
+            }
+            e
+        })
+    }
+}
+
+/// the writer's end, values leave the backlog once the writer calls `written`
+pub struct PoolDataReceiver {
+    rx: UnboundedReceiver<PoolUpdate>,
+    pub backlog: Arc<WriteBacklog>,
+    pub rows: Arc<RowCounts>,
+    pub writes: Arc<WriteCounts>,
+}
+
+impl PoolDataReceiver {
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<PoolUpdate>> {
+        self.rx.poll_recv(cx)
+    }
+
+    /// values the writer produced itself, e.g. candles
+    pub fn added(&self, values: &[PoolData]) {
+        self.backlog.add(values);
+        self.rows.add(values);
+    }
+
+    /// values that were written or dropped
+    pub fn written(&self, values: &[PoolData]) {
+        self.backlog.remove(values);
+    }
+}
+
+pub fn pool_data_channel(backlog: WriteBacklog) -> (PoolDataSender, PoolDataReceiver) {
+    let (tx, rx) = unbounded_channel();
+    let backlog = Arc::new(backlog);
+    let rows = Arc::new(RowCounts::default());
+    let writes = Arc::new(WriteCounts::default());
+
+    (
+        PoolDataSender {
+            tx,
+            backlog: backlog.clone(),
+            rows: rows.clone(),
+            writes: writes.clone(),
+        },
+        PoolDataReceiver {
+            rx,
+            backlog,
+            rows,
+            writes,
+        },
+    )
+}
@@ -0,0 +1,558 @@
+use std::collections::{BTreeMap, HashMap};
+
+use alloy_primitives::{Address, I256};
+use clap::ValueEnum;
+use malachite::{
+    num::{arithmetic::traits::Pow, conversion::traits::RoundingFrom},
+    rounding_modes::RoundingMode,
+    Natural, Rational,
+};
+use tracing::{debug, info};
+
+use crate::{
+    handler::REORG_WINDOW,
+    pools::types::{PoolBlockData, PoolCandle, PoolData, PoolSlot0, PoolTrade},
+    utils::{u256_to_natural, TokenInfo},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum CandleInterval {
+    #[value(name = "block")]
+    Block,
+    #[value(name = "1m")]
+    Minute,
+    #[value(name = "1h")]
+    Hour,
+    #[value(name = "1d")]
+    Day,
+}
+
+impl CandleInterval {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CandleInterval::Block => "block",
+            CandleInterval::Minute => "1m",
+            CandleInterval::Hour => "1h",
+            CandleInterval::Day => "1d",
+        }
+    }
+
+    fn seconds(&self) -> Option<u64> {
+        match self {
+            CandleInterval::Block => None,
+            CandleInterval::Minute => Some(60),
+            CandleInterval::Hour => Some(3600),
+            CandleInterval::Day => Some(86400),
+        }
+    }
+
+    fn bucket_start(&self, block_number: u64, block_timestamp: u64) -> u64 {
+        match self.seconds() {
+            Some(secs) => block_timestamp - block_timestamp % secs,
+            None => block_number,
+        }
+    }
+
+    /// a bucket is closed once every block that could fall into it has been seen
+    fn is_closed(&self, bucket_start: u64, completed_block: u64, completed_timestamp: u64) -> bool {
+        match self.seconds() {
+            Some(secs) => completed_timestamp + 1 >= bucket_start + secs,
+            None => completed_block >= bucket_start,
+        }
+    }
+}
+
+/// a candle's pool, interval and bucket
+type CandleKey = (Address, CandleInterval, u64);
+
+/// builds OHLCV candles from the slot0 and trade values of each block
+///
+/// blocks complete out of order, so a candle is only emitted once every block up
+/// to the end of its bucket has been received
+pub struct CandleAggregator {
+    pub intervals: Vec<CandleInterval>,
+    pub pool_tokens: HashMap<Address, (TokenInfo, TokenInfo)>,
+    candles: HashMap<CandleKey, CandleBuilder>,
+    /// the emitted candles that end in the last `REORG_WINDOW` blocks, reopened by a reorg
+    /// that only takes back some of their blocks
+    emitted: HashMap<CandleKey, CandleBuilder>,
+    /// the close of each pool's last emitted candle and its end block, the price of a candle
+    /// with trades but no slot0 value
+    last_close: HashMap<(Address, CandleInterval), (u64, f64)>,
+    pending_blocks: BTreeMap<u64, u64>,
+    next_block: u64,
+    last_completed: Option<(u64, u64)>,
+}
+
+impl CandleAggregator {
+    pub fn new(
+        intervals: Vec<CandleInterval>,
+        pool_tokens: HashMap<Address, (TokenInfo, TokenInfo)>,
+        start_block: u64,
+    ) -> Self {
+        Self {
+            intervals,
+            pool_tokens,
+            candles: HashMap::new(),
+            emitted: HashMap::new(),
+            last_close: HashMap::new(),
+            pending_blocks: BTreeMap::new(),
+            next_block: start_block,
+            last_completed: None,
+        }
+    }
+
+    /// adds the block's values and returns any candles that were closed by it
+    pub fn on_block(&mut self, block: &PoolBlockData) -> Vec<PoolData> {
+        for val in &block.data {
+            match val {
+                PoolData::Slot0(slot0) => self.add_slot0(block.block_timestamp, slot0),
+                PoolData::Trade(trade) => self.add_trade(block.block_timestamp, trade),
+                _ => (),
+            }
+        }
+
+        self.pending_blocks
+            .insert(block.block_number, block.block_timestamp);
+        while let Some(timestamp) = self.pending_blocks.remove(&self.next_block) {
+            self.last_completed = Some((self.next_block, timestamp));
+            self.next_block += 1;
+        }
+
+        let Some((completed_block, completed_timestamp)) = self.last_completed else {
+            return Vec::new();
+        };
+
+        // in bucket order, so each candle gets the close of the one before
+        let mut closed = self
+            .candles
+            .keys()
+            .filter(|(_, interval, bucket_start)| {
+                interval.is_closed(*bucket_start, completed_block, completed_timestamp)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        closed.sort_by_key(|(_, _, bucket_start)| *bucket_start);
+
+        let mut candles = Vec::new();
+        for key in closed {
+            let Some(candle) = self.candles.remove(&key) else {
+                continue;
+            };
+            let (pool_address, interval, bucket_start) = key;
+            let previous_close = self
+                .last_close
+                .get(&(pool_address, interval))
+                .map(|(_, close)| *close);
+            let tokens = self
+                .pool_tokens
+                .get(&pool_address)
+                .map(|(token0, token1)| (token0.address, token1.address));
+
+            match candle.build(previous_close, tokens) {
+                Some(built) => {
+                    self.last_close
+                        .insert((pool_address, interval), (built.end_block, built.close));
+                    candles.push(built.into());
+                }
+                None => {
+                    debug!(target: "uniV3::candles", "dropping the {} candle at {bucket_start} of pool {:?}, it has trades but no price before it", interval.name(), pool_address)
+                }
+            }
+            self.emitted.insert(key, candle);
+        }
+        self.emitted
+            .retain(|_, candle| candle.end_block() + REORG_WINDOW as u64 >= completed_block);
+
+        candles
+    }
+
+    /// takes back the values of every block from `from_block`
+    ///
+    /// the sinks retract the candles that start at or after `from_block`, a candle that started
+    /// before it keeps its earlier blocks and is emitted again, replacing the retracted row by
+    /// its key, once the re-processed blocks close it
+    pub fn retract(&mut self, from_block: u64) {
+        self.candles.retain(|_, candle| candle.retract(from_block));
+        for (key, mut candle) in std::mem::take(&mut self.emitted) {
+            if candle.end_block() < from_block {
+                self.emitted.insert(key, candle);
+            } else if candle.retract(from_block) {
+                self.candles.insert(key, candle);
+            }
+        }
+        self.last_close
+            .retain(|_, (end_block, _)| *end_block < from_block);
+        self.pending_blocks.split_off(&from_block);
+
+        if self.next_block > from_block {
+            self.next_block = from_block;
+            self.last_completed = None;
+        }
+    }
+
+    /// drops the candles whose bucket never closed, they only have the blocks of this run and
+    /// would replace the complete row a run over the whole bucket writes
+    pub fn finish(&mut self) {
+        if !self.candles.is_empty() {
+            info!(target: "uniV3::candles", "not writing {} candles whose buckets the run didn't finish", self.candles.len());
+        }
+        self.candles.clear();
+    }
+
+    fn add_slot0(&mut self, block_timestamp: u64, slot0: &PoolSlot0) {
+        for interval in self.intervals.clone() {
+            let bucket_start = interval.bucket_start(slot0.block_number, block_timestamp);
+            self.candles
+                .entry((slot0.pool_address, interval, bucket_start))
+                .or_insert_with(|| CandleBuilder::new(slot0.pool_address, interval, bucket_start))
+                .add_price(slot0);
+        }
+    }
+
+    fn add_trade(&mut self, block_timestamp: u64, trade: &PoolTrade) {
+        let Some((token0, token1)) = self.pool_tokens.get(&trade.pool_address) else {
+            return;
+        };
+
+        let (amount0, amount1) = if trade.token_in == token0.address {
+            (trade.token_in_amount, trade.token_out_amount)
+        } else {
+            (trade.token_out_amount, trade.token_in_amount)
+        };
+        let volume0 = to_decimal_adjusted(amount0, token0.decimals);
+        let volume1 = to_decimal_adjusted(amount1, token1.decimals);
+
+        for interval in self.intervals.clone() {
+            let bucket_start = interval.bucket_start(trade.block_number, block_timestamp);
+            self.candles
+                .entry((trade.pool_address, interval, bucket_start))
+                .or_insert_with(|| CandleBuilder::new(trade.pool_address, interval, bucket_start))
+                .add_volume(trade.block_number, volume0, volume1);
+        }
+    }
+}
+
+fn to_decimal_adjusted(amount: I256, decimals: u8) -> f64 {
+    let amount = u256_to_natural(amount.unsigned_abs());
+    f64::rounding_from(
+        Rational::from_naturals(amount, Natural::from(10u8).pow(decimals as u64)),
+        RoundingMode::Nearest,
+    )
+    .0
+}
+
+/// a block's part of a candle
+#[derive(Debug, Clone)]
+struct BlockCandle {
+    /// the first and last price of the block by transaction index
+    open: Option<(u64, f64)>,
+    close: Option<(u64, f64)>,
+    high: f64,
+    low: f64,
+    volume_token0: f64,
+    volume_token1: f64,
+    trade_count: u64,
+}
+
+impl Default for BlockCandle {
+    fn default() -> Self {
+        Self {
+            open: None,
+            close: None,
+            high: f64::MIN,
+            low: f64::MAX,
+            volume_token0: 0.0,
+            volume_token1: 0.0,
+            trade_count: 0,
+        }
+    }
+}
+
+/// keeps each block's part of the candle apart, so a reorg only takes back the reorged blocks
+struct CandleBuilder {
+    pool_address: Address,
+    interval: CandleInterval,
+    bucket_start: u64,
+    tokens: Option<(Address, Address)>,
+    blocks: BTreeMap<u64, BlockCandle>,
+}
+
+impl CandleBuilder {
+    fn new(pool_address: Address, interval: CandleInterval, bucket_start: u64) -> Self {
+        Self {
+            pool_address,
+            interval,
+            bucket_start,
+            tokens: None,
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    fn end_block(&self) -> u64 {
+        self.blocks.keys().next_back().copied().unwrap_or_default()
+    }
+
+    /// drops the blocks from `from_block`, returning whether any block is left
+    fn retract(&mut self, from_block: u64) -> bool {
+        self.blocks.split_off(&from_block);
+        !self.blocks.is_empty()
+    }
+
+    fn add_price(&mut self, slot0: &PoolSlot0) {
+        self.tokens = Some((slot0.token0, slot0.token1));
+
+        let block = self.blocks.entry(slot0.block_number).or_default();
+        let (tx_index, price) = (slot0.tx_index, slot0.calculated_price);
+
+        if block.open.map(|(i, _)| tx_index < i).unwrap_or(true) {
+            block.open = Some((tx_index, price));
+        }
+        if block.close.map(|(i, _)| tx_index > i).unwrap_or(true) {
+            block.close = Some((tx_index, price));
+        }
+        block.high = block.high.max(price);
+        block.low = block.low.min(price);
+    }
+
+    fn add_volume(&mut self, block_number: u64, volume0: f64, volume1: f64) {
+        let block = self.blocks.entry(block_number).or_default();
+        block.volume_token0 += volume0;
+        block.volume_token1 += volume1;
+        block.trade_count += 1;
+    }
+
+    /// a candle without a slot0 price has the close of the candle before it as its prices, and
+    /// is dropped if there's none
+    fn build(
+        &self,
+        previous_close: Option<f64>,
+        tokens: Option<(Address, Address)>,
+    ) -> Option<PoolCandle> {
+        let (token0, token1) = self.tokens.or(tokens)?;
+        let (open, high, low, close) = match (
+            self.blocks.values().find_map(|block| block.open),
+            self.blocks.values().rev().find_map(|block| block.close),
+        ) {
+            (Some((_, open)), Some((_, close))) => (
+                open,
+                self.blocks
+                    .values()
+                    .map(|block| block.high)
+                    .fold(f64::MIN, f64::max),
+                self.blocks
+                    .values()
+                    .map(|block| block.low)
+                    .fold(f64::MAX, f64::min),
+                close,
+            ),
+            _ => {
+                let price = previous_close?;
+                (price, price, price, price)
+            }
+        };
+
+        Some(PoolCandle {
+            pool_address: self.pool_address,
+            interval: self.interval.name().to_string(),
+            bucket_start: self.bucket_start,
+            start_block: *self.blocks.keys().next()?,
+            end_block: self.end_block(),
+            token0,
+            token1,
+            open,
+            high,
+            low,
+            close,
+            volume_token0: self.blocks.values().map(|block| block.volume_token0).sum(),
+            volume_token1: self.blocks.values().map(|block| block.volume_token1).sum(),
+            trade_count: self.blocks.values().map(|block| block.trade_count).sum(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::{TxHash, U256};
+
+    use super::*;
+    use crate::pools::types::trade;
+
+    fn slot0(block_number: u64, tx_index: u64, price: f64) -> PoolData {
+        PoolData::Slot0(PoolSlot0 {
+            block_number,
+            pool_address: Address::with_last_byte(1),
+            token0: Address::with_last_byte(2),
+            token0_decimals: 6,
+            token1: Address::with_last_byte(3),
+            token1_decimals: 18,
+            tx_hash: TxHash::ZERO,
+            tx_index,
+            tick: 0,
+            sqrt_price_x96: U256::ZERO,
+            calculated_price: price,
+            observation_index: 0,
+            observation_cardinality: 0,
+            observation_cardinality_next: 0,
+            fee_protocol: 0,
+            unlocked: true,
+        })
+    }
+
+    #[test]
+    fn test_minute_candles_out_of_order() {
+        let pool_tokens = HashMap::from([(
+            Address::with_last_byte(1),
+            (
+                TokenInfo::new(Address::with_last_byte(2), 6),
+                TokenInfo::new(Address::with_last_byte(3), 18),
+            ),
+        )]);
+        let mut aggregator = CandleAggregator::new(vec![CandleInterval::Minute], pool_tokens, 100);
+
+        let block_101 = PoolBlockData::new(101, 1200, vec![slot0(101, 4, 3.0), slot0(101, 1, 5.0)]);
+        assert!(aggregator.on_block(&block_101).is_empty());
+
+        let block_100 = PoolBlockData::new(100, 1188, vec![slot0(100, 0, 2.0)]);
+        let closed = aggregator.on_block(&block_100);
+
+        let expected = PoolData::Candle(PoolCandle {
+            pool_address: Address::with_last_byte(1),
+            interval: "1m".to_string(),
+            bucket_start: 1140,
+            start_block: 100,
+            end_block: 100,
+            token0: Address::with_last_byte(2),
+            token1: Address::with_last_byte(3),
+            open: 2.0,
+            high: 2.0,
+            low: 2.0,
+            close: 2.0,
+            volume_token0: 0.0,
+            volume_token1: 0.0,
+            trade_count: 0,
+        });
+        assert_eq!(closed, vec![expected]);
+
+        let block_102 = PoolBlockData::new(102, 1212, vec![slot0(102, 0, 7.0)]);
+        assert!(aggregator.on_block(&block_102).is_empty());
+
+        let remaining = aggregator.on_block(&PoolBlockData::new(103, 1260, Vec::new()));
+        let PoolData::Candle(candle) = &remaining[0] else {
+            panic!("expected a candle")
+        };
+        assert_eq!(
+            (candle.open, candle.high, candle.low, candle.close),
+            (5.0, 7.0, 3.0, 7.0)
+        );
+    }
+
+    #[test]
+    fn test_trade_only_candle_takes_the_previous_close() {
+        let pool_tokens = HashMap::from([(
+            Address::with_last_byte(1),
+            (
+                TokenInfo::new(Address::with_last_byte(2), 6),
+                TokenInfo::new(Address::with_last_byte(3), 18),
+            ),
+        )]);
+        let mut aggregator = CandleAggregator::new(vec![CandleInterval::Minute], pool_tokens, 100);
+
+        // a trade before any price has nothing to take its prices from
+        assert!(aggregator
+            .on_block(&PoolBlockData::new(100, 1100, vec![trade(100, 1_000_000)]))
+            .is_empty());
+        // which is dropped once block 101 closes its bucket
+        let closed = aggregator.on_block(&PoolBlockData::new(101, 1188, vec![slot0(101, 0, 2.0)]));
+        assert!(closed.is_empty());
+
+        aggregator.on_block(&PoolBlockData::new(102, 1200, vec![trade(102, 1_000_000)]));
+        let closed = aggregator.on_block(&PoolBlockData::new(103, 1260, vec![slot0(103, 0, 3.0)]));
+        let PoolData::Candle(candle) = &closed[0] else {
+            panic!("expected a candle")
+        };
+        assert_eq!(
+            (
+                candle.bucket_start,
+                candle.open,
+                candle.close,
+                candle.volume_token0,
+                candle.trade_count
+            ),
+            (1200, 2.0, 2.0, 1.0, 1)
+        );
+
+        // the bucket of block 103 never closed, so it isn't written
+        aggregator.finish();
+        assert!(aggregator.candles.is_empty());
+    }
+
+    #[test]
+    fn test_retract_only_takes_back_the_reorged_blocks() {
+        let tokens = (
+            TokenInfo::new(Address::with_last_byte(2), 6),
+            TokenInfo::new(Address::with_last_byte(3), 18),
+        );
+        let pool_tokens = HashMap::from([
+            (Address::with_last_byte(1), tokens.clone()),
+            (Address::with_last_byte(4), tokens),
+        ]);
+        let mut aggregator = CandleAggregator::new(vec![CandleInterval::Minute], pool_tokens, 100);
+
+        let other_pool = |block_number, price| {
+            let PoolData::Slot0(mut slot0) = slot0(block_number, 0, price) else {
+                unreachable!()
+            };
+            slot0.pool_address = Address::with_last_byte(4);
+            PoolData::Slot0(slot0)
+        };
+        let candles = |closed: Vec<PoolData>| {
+            let mut candles = closed
+                .into_iter()
+                .map(|candle| {
+                    let PoolData::Candle(candle) = candle else {
+                        panic!("expected a candle")
+                    };
+                    (
+                        candle.pool_address[19],
+                        candle.start_block,
+                        candle.end_block,
+                        candle.open,
+                        candle.close,
+                    )
+                })
+                .collect::<Vec<_>>();
+            candles.sort_by_key(|(pool, ..)| *pool);
+            candles
+        };
+
+        assert!(aggregator
+            .on_block(&PoolBlockData::new(100, 1200, vec![slot0(100, 0, 2.0)]))
+            .is_empty());
+        assert!(aggregator
+            .on_block(&PoolBlockData::new(
+                101,
+                1212,
+                vec![slot0(101, 0, 3.0), other_pool(101, 3.0)]
+            ))
+            .is_empty());
+        assert_eq!(
+            candles(aggregator.on_block(&PoolBlockData::new(102, 1260, Vec::new()))),
+            vec![(1, 100, 101, 2.0, 3.0), (4, 101, 101, 3.0, 3.0)]
+        );
+
+        // the first pool's emitted candle keeps block 100, the other pool's is gone
+        aggregator.retract(101);
+        assert!(aggregator
+            .on_block(&PoolBlockData::new(
+                101,
+                1212,
+                vec![slot0(101, 0, 4.0), other_pool(101, 6.0)]
+            ))
+            .is_empty());
+        assert_eq!(
+            candles(aggregator.on_block(&PoolBlockData::new(102, 1260, Vec::new()))),
+            vec![(1, 100, 101, 2.0, 4.0), (4, 101, 101, 6.0, 6.0)]
+        );
+    }
+}
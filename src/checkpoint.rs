@@ -0,0 +1,364 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use alloy_primitives::Address;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::pools::{FetcherKind, TrackedPool};
+
+/// the last block of each pool's fetchers whose values have all been inserted, every
+/// block from the pool's creation up to it is complete
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub pools: HashMap<Address, HashMap<FetcherKind, u64>>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// writes to a temporary file first so a crash mid-write never leaves a partial checkpoint
+    pub fn save(&self, path: &Path) -> eyre::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn completed_block(&self, pool_address: Address, kind: FetcherKind) -> Option<u64> {
+        self.pools.get(&pool_address)?.get(&kind).copied()
+    }
+
+    /// the block the fetcher picks up from, the pool's creation block if it has no checkpoint
+    pub fn start_block(&self, pool: &TrackedPool, kind: FetcherKind) -> u64 {
+        self.completed_block(pool.pool.pool_address, kind)
+            .map(|block| block + 1)
+            .unwrap_or(pool.pool.creation_block)
+            .max(pool.pool.creation_block)
+    }
+
+    /// whether a run of the fetcher from `start_block` continues its checkpoint, a later start
+    /// would jump over the blocks in between
+    pub fn continues(&self, pool: &TrackedPool, kind: FetcherKind, start_block: u64) -> bool {
+        start_block <= self.start_block(pool, kind)
+    }
+}
+
+/// tracks the blocks confirmed by the db and advances the checkpoint of every fetcher that
+/// has started once a contiguous run of blocks is complete
+///
+/// only the fetchers whose run continues their checkpoint are tracked, see
+/// `Checkpoint::continues`, and the checkpoint never moves past a block that was given up on
+pub struct CheckpointTracker {
+    pub path: PathBuf,
+    pub checkpoint: Checkpoint,
+    /// the pool, fetcher and first block of each fetcher in the run
+    pub fetchers: Vec<(Address, FetcherKind, u64)>,
+    start_block: u64,
+    pending_blocks: BTreeSet<u64>,
+    next_block: u64,
+    /// blocks whose values were given up on, `--resume` has to re-run them
+    failed_blocks: BTreeSet<u64>,
+}
+
+impl CheckpointTracker {
+    pub fn new(
+        path: PathBuf,
+        checkpoint: Checkpoint,
+        fetchers: Vec<(Address, FetcherKind, u64)>,
+        start_block: u64,
+    ) -> Self {
+        Self {
+            path,
+            checkpoint,
+            fetchers,
+            start_block,
+            pending_blocks: BTreeSet::new(),
+            next_block: start_block,
+            failed_blocks: BTreeSet::new(),
+        }
+    }
+
+    /// the last block of the contiguous run before any failed block, if any
+    pub fn completed_block(&self) -> Option<u64> {
+        let end = match self.failed_blocks.first() {
+            Some(failed) => self.next_block.min(*failed),
+            None => self.next_block,
+        };
+        (end > self.start_block).then(|| end - 1)
+    }
+
+    /// the last block of the contiguous run, counting the failed blocks, whose values will
+    /// never be inserted in this run
+    pub fn written_block(&self) -> Option<u64> {
+        (self.next_block > self.start_block).then(|| self.next_block - 1)
+    }
+
+    /// marks the blocks whose values were inserted, saving the checkpoint if it moved
+    pub fn confirm(&mut self, blocks: impl IntoIterator<Item = u64>) -> eyre::Result<()> {
+        self.pending_blocks.extend(blocks);
+
+        let previous = self.completed_block();
+        while self.pending_blocks.remove(&self.next_block) {
+            self.next_block += 1;
+        }
+        let Some(completed_block) = self.completed_block() else {
+            return Ok(());
+        };
+        if previous == Some(completed_block) {
+            return Ok(());
+        }
+
+        for (pool_address, kind, start_block) in &self.fetchers {
+            if *start_block <= completed_block {
+                // a run over earlier blocks never moves the checkpoint back
+                let block = self
+                    .checkpoint
+                    .pools
+                    .entry(*pool_address)
+                    .or_default()
+                    .entry(*kind)
+                    .or_default();
+                *block = (*block).max(completed_block);
+            }
+        }
+        self.checkpoint.save(&self.path)?;
+        debug!(target: "uniV3::checkpoint", "checkpointed block {completed_block}");
+
+        Ok(())
+    }
+
+    /// marks the blocks whose values were given up on, the checkpoint stays before the first
+    /// of them so a `--resume` re-runs them
+    pub fn fail(&mut self, blocks: impl IntoIterator<Item = u64>) -> eyre::Result<()> {
+        let blocks = blocks.into_iter().collect::<Vec<_>>();
+        let Some(first) = blocks.iter().min().copied() else {
+            return Ok(());
+        };
+        if self
+            .failed_blocks
+            .first()
+            .map_or(true, |failed| first < *failed)
+        {
+            warn!(target: "uniV3::checkpoint", "not checkpointing past block {first}, `--resume` will re-run the blocks that were given up on");
+        }
+        self.failed_blocks.extend(blocks.iter().copied());
+
+        // the later blocks still complete the contiguous run, they are re-run with the failed ones
+        self.confirm(blocks)
+    }
+
+    /// forgets every block from `from_block`, moving the checkpoints back before it
+    pub fn rewind(&mut self, from_block: u64) -> eyre::Result<()> {
+        self.pending_blocks.split_off(&from_block);
+        // the reorged blocks are processed again, so they are no longer failed
+        self.failed_blocks.split_off(&from_block);
+        if self.next_block <= from_block {
+            return Ok(());
+        }
+        self.next_block = from_block.max(self.start_block);
+
+        let Some(completed_block) = from_block.checked_sub(1) else {
+            return Ok(());
+        };
+        for (pool_address, kind, _) in &self.fetchers {
+            if let Some(block) = self
+                .checkpoint
+                .pools
+                .get_mut(pool_address)
+                .and_then(|fetchers| fetchers.get_mut(kind))
+            {
+                *block = (*block).min(completed_block);
+            }
+        }
+        self.checkpoint.save(&self.path)
+    }
+
+    pub fn log_completed(&self) {
+        if let Some(failed) = self.failed_blocks.first() {
+            warn!(target: "uniV3::checkpoint", "gave up on {} blocks, `--resume` will re-run every block from {failed}", self.failed_blocks.len());
+        }
+        match self.completed_block() {
+            Some(block) => {
+                info!(target: "uniV3::checkpoint", "checkpointed up to block {block} in {}", self.path.display())
+            }
+            None => info!(target: "uniV3::checkpoint", "no blocks were checkpointed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::InitialPools, utils::temp_path};
+
+    #[test]
+    fn test_checkpoint_advances_contiguously() {
+        let path = temp_path("checkpoint.json");
+        let pool = Address::with_last_byte(1);
+        let late_pool = Address::with_last_byte(2);
+
+        let mut tracker = CheckpointTracker::new(
+            path.clone(),
+            Checkpoint::default(),
+            vec![
+                (pool, FetcherKind::Slot0, 100),
+                (late_pool, FetcherKind::Trades, 103),
+            ],
+            100,
+        );
+
+        tracker.confirm([101, 102]).unwrap();
+        assert_eq!(tracker.completed_block(), None);
+        assert!(tracker.checkpoint.pools.is_empty());
+
+        tracker.confirm([100]).unwrap();
+        assert_eq!(
+            tracker.checkpoint.completed_block(pool, FetcherKind::Slot0),
+            Some(102)
+        );
+        assert_eq!(
+            tracker
+                .checkpoint
+                .completed_block(late_pool, FetcherKind::Trades),
+            None
+        );
+
+        tracker.confirm([103]).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), tracker.checkpoint);
+        assert_eq!(
+            tracker
+                .checkpoint
+                .completed_block(late_pool, FetcherKind::Trades),
+            Some(103)
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rewind_forgets_reorged_blocks() {
+        let path = temp_path("checkpoint.json");
+        let pool = Address::with_last_byte(1);
+
+        let mut tracker = CheckpointTracker::new(
+            path.clone(),
+            Checkpoint::default(),
+            vec![(pool, FetcherKind::Slot0, 100)],
+            100,
+        );
+        tracker.confirm([100, 101, 102, 104]).unwrap();
+        assert_eq!(tracker.completed_block(), Some(102));
+
+        tracker.rewind(102).unwrap();
+        assert_eq!(tracker.completed_block(), Some(101));
+        assert_eq!(
+            Checkpoint::load(&path)
+                .unwrap()
+                .completed_block(pool, FetcherKind::Slot0),
+            Some(101)
+        );
+
+        // block 104 was pending when it was reorged, so it has to be confirmed again
+        tracker.confirm([102, 103]).unwrap();
+        assert_eq!(tracker.completed_block(), Some(103));
+        tracker.confirm([104]).unwrap();
+        assert_eq!(tracker.completed_block(), Some(104));
+
+        // a reorg of the first block of the run leaves nothing completed
+        tracker.rewind(100).unwrap();
+        assert_eq!(tracker.completed_block(), None);
+        assert_eq!(
+            tracker.checkpoint.completed_block(pool, FetcherKind::Slot0),
+            Some(99)
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_stays_before_failed_blocks() {
+        let path = temp_path("checkpoint.json");
+        let pool = Address::with_last_byte(1);
+
+        let mut tracker = CheckpointTracker::new(
+            path.clone(),
+            Checkpoint::default(),
+            vec![(pool, FetcherKind::Slot0, 100)],
+            100,
+        );
+        tracker.confirm([100, 101]).unwrap();
+        tracker.fail([102]).unwrap();
+        tracker.confirm([103, 104]).unwrap();
+        assert_eq!(tracker.completed_block(), Some(101));
+        assert_eq!(
+            Checkpoint::load(&path)
+                .unwrap()
+                .completed_block(pool, FetcherKind::Slot0),
+            Some(101)
+        );
+
+        // a reorg before the failed block re-runs it, so the checkpoint can move past it again
+        tracker.rewind(101).unwrap();
+        tracker.confirm(101..=104).unwrap();
+        assert_eq!(tracker.completed_block(), Some(104));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_continues_without_gaps() {
+        let pool = TrackedPool::new(
+            InitialPools {
+                pool_address: Address::with_last_byte(1),
+                token0_address: Address::with_last_byte(2),
+                token0_decimals: 18,
+                token1_address: Address::with_last_byte(3),
+                token1_decimals: 6,
+                creation_block: 100,
+            },
+            vec![FetcherKind::Slot0, FetcherKind::Trades],
+        );
+        let mut checkpoint = Checkpoint::default();
+        checkpoint
+            .pools
+            .entry(pool.pool.pool_address)
+            .or_default()
+            .insert(FetcherKind::Slot0, 500);
+
+        assert!(checkpoint.continues(&pool, FetcherKind::Slot0, 300));
+        assert!(checkpoint.continues(&pool, FetcherKind::Slot0, 501));
+        assert!(!checkpoint.continues(&pool, FetcherKind::Slot0, 502));
+
+        // without a checkpoint the fetcher has to start from the pool's creation
+        assert!(checkpoint.continues(&pool, FetcherKind::Trades, 100));
+        assert!(!checkpoint.continues(&pool, FetcherKind::Trades, 101));
+
+        // re-running earlier blocks keeps the later checkpoint
+        let path = temp_path("checkpoint.json");
+        let mut tracker = CheckpointTracker::new(
+            path.clone(),
+            checkpoint,
+            vec![(pool.pool.pool_address, FetcherKind::Slot0, 300)],
+            300,
+        );
+        tracker.confirm(300..=310).unwrap();
+        assert_eq!(
+            tracker
+                .checkpoint
+                .completed_block(pool.pool.pool_address, FetcherKind::Slot0),
+            Some(500)
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,11 +1,29 @@
-use clap::{ArgAction, Args, Parser};
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::{ArgAction, Args, Parser, Subcommand};
+
+use crate::{
+    candles::CandleInterval,
+    concurrency::ConcurrencyLimits,
+    filters::PoolFilters,
+    pools::{ChangeDetection, FetcherKind},
+    query::PoolStateQuery,
+    retry::RetryPolicy,
+    schema::ClickhouseSchema,
+    sinks::{parquet::ParquetArgs, sqlite::SqliteArgs, tcp::TcpArgs, text::TextArgs, SinkKind},
+};
 
 use tracing::{level_filters::LevelFilter, Level};
 use tracing_subscriber::filter::Directive;
 
+/// the flags process the blocks, so none of them can be given with a subcommand, except the
+/// global `--clickhouse-*` and verbosity ones
 #[derive(Debug, Parser)]
-#[command(about = "Uniswap V3 Pool Calls", long_about = None)]
+#[command(about = "Uniswap V3 Pool Calls", long_about = None, args_conflicts_with_subcommands = true)]
 pub struct CliCmd {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// calls `slot0()` on the UniV3 contract after each transaction that altered the pool's state
     #[arg(short = 'l', long, default_value = "false")]
     pub slot0: bool,
@@ -18,29 +36,156 @@ pub struct CliCmd {
     #[arg(short, long, default_value = "false")]
     pub trades: bool,
 
+    /// `.toml` or `.json` manifest of the pools to track and the fetchers to run for each
+    #[arg(long, conflicts_with = "discover")]
+    pub pools: Option<PathBuf>,
+
+    /// discovers the pools from the `PoolCreated` events of the UniswapV3 factory instead of using the predefined pool list
+    #[arg(long, default_value = "false")]
+    pub discover: bool,
+
+    /// local cache of the pool and token metadata resolved from the chain
+    #[arg(long, default_value = "pool_metadata.json")]
+    pub metadata_cache: PathBuf,
+
+    /// aggregates the slot0 prices and trades of each pool into OHLCV candles at the given intervals (block, 1m, 1h, 1d)
+    #[arg(long, value_delimiter = ',', requires = "slot0")]
+    pub candles: Vec<CandleInterval>,
+
+    /// how the slot0 and tick info fetchers find the transactions that changed a pool
+    ///
+    /// `state-diff` checks the pool's storage after replaying each transaction, so blocks are
+    /// only traced for the trades fetcher
+    #[arg(long, value_enum, default_value_t = ChangeDetection::Traces)]
+    pub change_detection: ChangeDetection,
+
+    /// number of consecutive blocks each task processes in order, carrying the replayed state
+    /// from one block to the next instead of loading the state at each block's parent, blocks
+    /// before the merge (15537394) are always processed one at a time
+    #[arg(long, default_value = "1")]
+    pub range_size: u64,
+
+    /// where the last completed block of each pool's fetchers is saved once its values are inserted
+    #[arg(long, default_value = "checkpoint.json")]
+    pub checkpoint: PathBuf,
+
+    /// continues each pool's fetchers from the block after their checkpoint
+    #[arg(long, default_value = "false", conflicts_with = "start_block")]
+    pub resume: bool,
+
     /// default is the block of the creation of the first uniV3 pool
     #[arg(short, long)]
     pub start_block: Option<u64>,
 
-    /// defaults is the current chain tip
+    /// defaults is the current chain tip (less `--confirmations` when following)
     #[arg(short, long)]
     pub end_block: Option<u64>,
 
+    /// keeps polling the reth db for new blocks once the block range is complete
+    #[arg(long, default_value = "false")]
+    pub follow: bool,
+
+    /// number of blocks behind the tip a block must be before it's processed when following
+    #[arg(long, default_value = "12", requires = "follow")]
+    pub confirmations: u64,
+
+    /// seconds between polls of the reth db for new blocks when following
+    #[arg(
+        long,
+        default_value = "12",
+        requires = "follow",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub poll_interval: u64,
+
+    /// where the values are written, any of `clickhouse`, `parquet`, `jsonl`, `csv`, `sqlite`, `tcp`
+    #[arg(
+        long = "sink",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "clickhouse"
+    )]
+    pub sinks: Vec<SinkKind>,
+
     /// size of the db buffer
     #[arg(long, default_value = "10000")]
     pub insert_size: usize,
 
-    /// the maximum concurrenct tasks to run at once
-    ///
-    /// reth sets it's mdbx enviroment's max readers to 32000
-    /// we set our default lower to account for errored blocks + multi reads
-    #[arg(short, long, default_value = "25000")]
-    pub max_concurrent_tasks: usize,
+    /// new blocks are not launched while more than this many values are waiting to be written
+    #[arg(long, default_value = "1000000")]
+    pub max_queued_rows: usize,
+
+    /// new blocks are not launched while the values waiting to be written take more than this many bytes
+    #[arg(long, default_value = "1073741824")]
+    pub max_queued_bytes: usize,
+
+    #[clap(flatten)]
+    pub filters: PoolFilters,
+
+    /// address to serve prometheus metrics on, e.g. `127.0.0.1:9001`
+    #[arg(long)]
+    pub metrics: Option<SocketAddr>,
+
+    /// seconds between progress reports
+    #[arg(long, default_value = "30", value_parser = clap::value_parser!(u64).range(1..))]
+    pub progress_interval: u64,
+
+    #[clap(flatten)]
+    pub clickhouse: ClickhouseSchema,
+
+    #[clap(flatten)]
+    pub parquet: ParquetArgs,
+
+    #[clap(flatten)]
+    pub text: TextArgs,
+
+    #[clap(flatten)]
+    pub sqlite: SqliteArgs,
+
+    #[clap(flatten)]
+    pub tcp: TcpArgs,
+
+    #[clap(flatten)]
+    pub concurrency: ConcurrencyLimits,
+
+    #[clap(flatten)]
+    pub retry: RetryPolicy,
 
     #[clap(flatten)]
     pub verbosity: Verbosity,
 }
 
+/// runs instead of processing the blocks
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// prints a pool's slot0, globals and ticks right after a transaction as json, without
+    /// writing anything
+    Query(PoolStateQuery),
+    /// creates the clickhouse database and tables, or adds their missing columns
+    Schema(SchemaCmd),
+}
+
+#[derive(Debug, Args)]
+pub struct SchemaCmd {
+    /// only verifies the tables, which every run writing to clickhouse does before it starts
+    #[arg(long, default_value = "false")]
+    pub check: bool,
+}
+
+impl CliCmd {
+    /// the fetchers enabled by the cli flags
+    pub fn fetchers(&self) -> Vec<FetcherKind> {
+        [
+            (self.slot0, FetcherKind::Slot0),
+            (self.tick_info, FetcherKind::TickInfo),
+            (self.trades, FetcherKind::Trades),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, kind)| enabled.then_some(kind))
+        .collect()
+    }
+}
+
 /// The verbosity settings for the cli.
 #[derive(Debug, Copy, Clone, Args)]
 #[command(next_help_heading = "Display")]
@@ -85,3 +230,39 @@ impl Verbosity {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::schema::TableEngine;
+
+    #[test]
+    fn test_cli() {
+        CliCmd::command().debug_assert();
+    }
+
+    #[test]
+    fn test_schema_takes_the_clickhouse_flags() {
+        let cli = CliCmd::try_parse_from([
+            "uniV3",
+            "schema",
+            "--clickhouse-cluster",
+            "none",
+            "--clickhouse-engine",
+            "replacing",
+        ])
+        .unwrap();
+
+        assert!(matches!(cli.command, Some(Command::Schema(_))));
+        assert_eq!(cli.clickhouse.cluster(), None);
+        assert_eq!(cli.clickhouse.clickhouse_engine, TableEngine::Replacing);
+    }
+
+    #[test]
+    fn test_subcommands_reject_the_run_flags() {
+        assert!(CliCmd::try_parse_from(["uniV3", "schema", "--sink", "parquet"]).is_err());
+        assert!(CliCmd::try_parse_from(["uniV3", "--follow", "schema"]).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use std::{task::Context, time::Duration};
+
+use clap::Args;
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{debug, info};
+
+/// how often the in-flight block limit is re-evaluated
+const ADJUST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// weight of the newest range in the per-block latency average
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// bounds the number of blocks in flight is adjusted within
+#[derive(Debug, Clone, PartialEq, Args)]
+#[command(next_help_heading = "Concurrency")]
+pub struct ConcurrencyLimits {
+    /// blocks that are always allowed in flight, the run starts from this many
+    #[arg(long, default_value = "16")]
+    pub min_concurrent_blocks: usize,
+
+    /// blocks the in-flight limit is raised up to while memory and latency allow it
+    ///
+    /// each in-flight range holds a read transaction on the reth db, so this also bounds the
+    /// readers open at once, reth's mdbx environment allows 32000
+    #[arg(short = 'm', long, default_value = "4096")]
+    pub max_concurrent_blocks: usize,
+
+    /// resident memory in MiB above which the in-flight limit is lowered, defaults to 3/4 of
+    /// the machine's memory
+    #[arg(long)]
+    pub max_memory_mb: Option<u64>,
+
+    /// the in-flight limit is lowered once the average per-block latency is this many times
+    /// the lowest average seen
+    #[arg(long, default_value = "3.0")]
+    pub max_latency_ratio: f64,
+}
+
+/// a measurement of the resources used by the blocks in flight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResourceSample {
+    pub in_flight_blocks: u64,
+    /// resident memory of the process in bytes, if it can be read
+    pub rss: Option<u64>,
+}
+
+/// raises the number of blocks in flight while there's headroom and lowers it when memory
+/// runs out or blocks slow down from contention
+pub struct AdaptiveConcurrency {
+    pub limits: ConcurrencyLimits,
+    /// resident memory in bytes
+    max_memory: Option<u64>,
+    limit: usize,
+    /// moving average of the seconds each block takes
+    latency: Option<f64>,
+    baseline_latency: Option<f64>,
+    interval: Interval,
+}
+
+impl AdaptiveConcurrency {
+    pub fn new(limits: ConcurrencyLimits) -> Self {
+        let max_memory = limits
+            .max_memory_mb
+            .map(|mb| mb << 20)
+            .or_else(|| total_memory().map(|total| total / 4 * 3));
+
+        let mut interval = tokio::time::interval(ADJUST_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let limit = limits.min_concurrent_blocks.max(1);
+        Self {
+            limits,
+            max_memory,
+            limit,
+            latency: None,
+            baseline_latency: None,
+            interval,
+        }
+    }
+
+    /// the number of blocks currently allowed in flight
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// whether another range can be launched
+    pub fn has_capacity(&self, in_flight_blocks: u64) -> bool {
+        in_flight_blocks < self.limit as u64
+    }
+
+    /// folds the time a completed range took into the per-block latency
+    pub fn record_latency(&mut self, blocks: u64, elapsed: Duration) {
+        if blocks == 0 {
+            return;
+        }
+
+        let per_block = elapsed.as_secs_f64() / blocks as f64;
+        let latency = match self.latency {
+            Some(latency) => latency + LATENCY_SMOOTHING * (per_block - latency),
+            None => per_block,
+        };
+        self.latency = Some(latency);
+    }
+
+    /// samples the process on each tick of the interval and adjusts the limit
+    pub fn poll_adjust(&mut self, cx: &mut Context<'_>, in_flight_blocks: u64) {
+        while self.interval.poll_tick(cx).is_ready() {
+            self.adjust(ResourceSample {
+                in_flight_blocks,
+                rss: resident_memory(),
+            });
+        }
+    }
+
+    /// halves the limit under memory or latency pressure, otherwise grows it by a quarter
+    /// while it's the only thing holding blocks back
+    pub fn adjust(&mut self, sample: ResourceSample) {
+        let previous = self.limit;
+
+        let over_memory = sample
+            .rss
+            .zip(self.max_memory)
+            .is_some_and(|(rss, max)| rss > max);
+
+        let slowed_down = match (self.latency, self.baseline_latency) {
+            (Some(latency), Some(baseline)) => latency > baseline * self.limits.max_latency_ratio,
+            _ => false,
+        };
+        if let Some(latency) = self.latency {
+            self.baseline_latency = Some(self.baseline_latency.map_or(latency, |b| b.min(latency)));
+        }
+
+        if over_memory || slowed_down {
+            self.limit /= 2;
+            // the slower blocks are the new normal until they speed up again
+            if slowed_down {
+                self.baseline_latency = self.latency;
+            }
+        } else if sample.in_flight_blocks >= self.limit as u64 {
+            self.limit += (self.limit / 4).max(1);
+        }
+
+        self.limit = self.limit.clamp(
+            self.limits.min_concurrent_blocks.max(1),
+            self.limits
+                .max_concurrent_blocks
+                .max(self.limits.min_concurrent_blocks)
+                .max(1),
+        );
+
+        if self.limit < previous {
+            info!(target: "uniV3", "lowered in-flight blocks from {previous} to {} - memory: {}, latency: {}", self.limit, format_memory(sample.rss), format_latency(self.latency));
+        } else if self.limit > previous {
+            debug!(target: "uniV3", "raised in-flight blocks from {previous} to {} - memory: {}, latency: {}", self.limit, format_memory(sample.rss), format_latency(self.latency));
+        }
+    }
+}
+
+fn format_memory(rss: Option<u64>) -> String {
+    rss.map(|rss| format!("{} MiB", rss >> 20))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn format_latency(latency: Option<f64>) -> String {
+    latency
+        .map(|latency| format!("{:.0}ms/block", latency * 1000.0))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// the resident memory of the process in bytes, none off linux
+pub fn resident_memory() -> Option<u64> {
+    read_kib_field("/proc/self/status", "VmRSS:")
+}
+
+/// the memory of the machine in bytes, none off linux
+pub fn total_memory() -> Option<u64> {
+    read_kib_field("/proc/meminfo", "MemTotal:")
+}
+
+fn read_kib_field(path: &str, field: &str) -> Option<u64> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    parse_kib_field(&contents, field)
+}
+
+fn parse_kib_field(contents: &str, field: &str) -> Option<u64> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix(field))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|kib| kib.trim().parse::<u64>().ok())
+        .map(|kib| kib << 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> ConcurrencyLimits {
+        ConcurrencyLimits {
+            min_concurrent_blocks: 4,
+            max_concurrent_blocks: 20,
+            max_memory_mb: Some(1024),
+            max_latency_ratio: 2.0,
+        }
+    }
+
+    fn sample(in_flight_blocks: u64, rss_mb: u64) -> ResourceSample {
+        ResourceSample {
+            in_flight_blocks,
+            rss: Some(rss_mb << 20),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_concurrency_bounds() {
+        let mut concurrency = AdaptiveConcurrency::new(limits());
+        assert_eq!(concurrency.limit(), 4);
+
+        // not raised while the limit isn't what's holding blocks back
+        concurrency.adjust(sample(2, 100));
+        assert_eq!(concurrency.limit(), 4);
+
+        concurrency.adjust(sample(4, 100));
+        assert_eq!(concurrency.limit(), 5);
+
+        for _ in 0..20 {
+            concurrency.adjust(sample(concurrency.limit() as u64, 100));
+        }
+        assert_eq!(concurrency.limit(), 20);
+
+        concurrency.adjust(sample(20, 2048));
+        assert_eq!(concurrency.limit(), 10);
+
+        concurrency.adjust(sample(10, 2048));
+        concurrency.adjust(sample(5, 2048));
+        assert_eq!(concurrency.limit(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_concurrency_latency() {
+        let mut concurrency = AdaptiveConcurrency::new(ConcurrencyLimits {
+            min_concurrent_blocks: 16,
+            max_concurrent_blocks: 64,
+            ..limits()
+        });
+
+        concurrency.record_latency(2, Duration::from_millis(200));
+        concurrency.adjust(sample(16, 100));
+        assert_eq!(concurrency.limit(), 20);
+
+        for _ in 0..20 {
+            concurrency.record_latency(1, Duration::from_secs(1));
+        }
+        concurrency.adjust(sample(20, 100));
+        assert_eq!(concurrency.limit(), 16);
+
+        // the slower latency is the new baseline
+        concurrency.adjust(sample(16, 100));
+        assert_eq!(concurrency.limit(), 20);
+    }
+
+    #[test]
+    fn test_parse_kib_field() {
+        let status = "Name:\tuniV3\nVmPeak:\t  2048 kB\nVmRSS:\t  1024 kB\n";
+        assert_eq!(parse_kib_field(status, "VmRSS:"), Some(1024 << 10));
+        assert_eq!(parse_kib_field(status, "VmSwap:"), None);
+    }
+}
@@ -21,13 +21,11 @@ pub const INITIAL_POOLS: &str = r#"WITH
 SELECT
     toString(p.address) AS pool_address,
     toString(p.tokens[1]) AS token0_address,
-    CAST(t0.decimals, 'UInt8') AS token0_decimals,
     toString(p.tokens[2]) AS token1_address,
-    CAST(t1.decimals, 'UInt8') AS token1_decimals,
     CAST(init_block, 'UInt64') AS creation_block
 FROM ethereum.pools p
 INNER JOIN initial_pools n ON n.pool = p.address
-INNER JOIN ethereum.dex_tokens t0 ON token0_address = t0.address
-INNER JOIN ethereum.dex_tokens t1 ON token1_address = t1.address
 "#;
 
+pub const TABLE_COLUMNS: &str = r#"SELECT name, type AS column_type FROM system.columns WHERE database = ? AND table = ? ORDER BY position"#;
+
@@ -1,63 +1,30 @@
-use std::{
-    pin::Pin,
-    sync::Arc,
-    task::{Context, Poll},
-};
-
-use crate::pools::types::PoolTrade;
+use crate::{schema::ClickhouseSchema, sinks::PoolDataSink};
 use alloy_primitives::Address;
+use async_trait::async_trait;
 use clickhouse::Row;
-use db_interfaces::{
-    clickhouse::{client::ClickhouseClient, config::ClickhouseConfig},
-    clickhouse_dbms, remote_clickhouse_table, Database,
-};
-use futures::{Future, FutureExt};
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::UnboundedReceiver;
-use tracing::{error, info};
+use tracing::{info, warn};
 
 use crate::{
-    const_sql::INITIAL_POOLS,
-    pools::types::{PoolData, PoolSlot0, PoolTickInfo},
+    const_sql::INITIAL_POOLS, metadata::PoolMetadataResolver, pools::types::PoolData,
     utils::serde_address,
 };
 
-clickhouse_dbms!(UniswapV3Tables, [UniV3TickInfo, UniV3Slot0, UniV3Trades]);
-
-remote_clickhouse_table!(
-    UniswapV3Tables,
-    "eth_analytics",
-    UniV3TickInfo,
-    PoolTickInfo,
-    "src/sql/tables/"
-);
-
-remote_clickhouse_table!(
-    UniswapV3Tables,
-    "eth_analytics",
-    UniV3Slot0,
-    PoolSlot0,
-    "src/sql/tables/"
-);
-
-remote_clickhouse_table!(
-    UniswapV3Tables,
-    "eth_analytics",
-    UniV3Trades,
-    PoolTrade,
-    "src/sql/tables/"
-);
-
-pub fn spawn_clickhouse_db() -> ClickhouseClient<UniswapV3Tables> {
+/// the one connection the pool list is read, the schema is applied and the values are
+/// written and retracted with
+pub fn spawn_clickhouse_db() -> clickhouse::Client {
     let url = std::env::var("CLICKHOUSE_URL").expect("CLICKHOUSE_URL not found in .env");
     let user = std::env::var("CLICKHOUSE_USER").expect("CLICKHOUSE_USER not found in .env");
     let pass = std::env::var("CLICKHOUSE_PASS").expect("CLICKHOUSE_PASS not found in .env");
 
-    let config = ClickhouseConfig::new(user, pass, url, true, None);
+    let client = clickhouse::Client::default()
+        .with_url(url)
+        .with_user(user)
+        .with_password(pass);
 
     info!(target: "uniV3", "started clickhouse db connection");
 
-    config.build()
+    client
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Row, PartialEq)]
@@ -73,105 +40,109 @@ pub struct InitialPools {
     pub creation_block: u64,
 }
 
-pub async fn get_initial_pools(
-    db: &ClickhouseClient<UniswapV3Tables>,
-) -> eyre::Result<(u64, Vec<InitialPools>)> {
-    let pools: Vec<InitialPools> = db.query_many(INITIAL_POOLS, &()).await?;
-
-    let min_block = pools.iter().map(|p| p.creation_block).min().unwrap();
+/// a predefined pool, whose token metadata is resolved on-chain
+#[derive(Debug, Clone, Serialize, Deserialize, Row, PartialEq)]
+pub struct InitialPoolAddresses {
+    #[serde(with = "serde_address")]
+    pub pool_address: Address,
+    #[serde(with = "serde_address")]
+    pub token0_address: Address,
+    #[serde(with = "serde_address")]
+    pub token1_address: Address,
+    pub creation_block: u64,
+}
 
-    Ok((min_block, pools))
+/// the predefined pools, skipping pools whose tokens fail to resolve
+pub async fn get_initial_pools(
+    db: &clickhouse::Client,
+    resolver: &mut PoolMetadataResolver,
+) -> eyre::Result<Vec<InitialPools>> {
+    let pools = db
+        .query(INITIAL_POOLS)
+        .fetch_all::<InitialPoolAddresses>()
+        .await?;
+
+    Ok(pools
+        .into_iter()
+        .filter_map(|pool| {
+            resolver
+                .resolve_tokens(
+                    pool.pool_address,
+                    pool.token0_address,
+                    pool.token1_address,
+                    pool.creation_block,
+                )
+                .map_err(|e| {
+                    warn!(target: "uniV3::metadata", "failed to resolve tokens for pool {:?} - {:?}", pool.pool_address, e)
+                })
+                .ok()
+        })
+        .collect())
 }
 
+/// the `uni_v3_*` tables of `--clickhouse-database`, fed in batches of `--insert-size` by the
+/// `BufferedWriter`
 pub struct BufferedClickhouse {
-    pub db: Arc<ClickhouseClient<UniswapV3Tables>>,
-    pub rx: UnboundedReceiver<Vec<PoolData>>,
-    pub fut: Option<Pin<Box<dyn Future<Output = eyre::Result<()>> + Send>>>,
-    pub queue: Vec<PoolData>,
-    pub inserting: Vec<PoolData>,
-    pub insert_size: usize,
+    pub client: clickhouse::Client,
+    /// the database and cluster the values are inserted into and retracted from
+    pub schema: ClickhouseSchema,
 }
+
 impl BufferedClickhouse {
-    pub fn new(
-        db: Arc<ClickhouseClient<UniswapV3Tables>>,
-        rx: UnboundedReceiver<Vec<PoolData>>,
-        insert_size: usize,
-    ) -> Self {
-        info!(target: "uniV3", "created buffered clickhouse connection");
-        Self {
-            db,
-            rx,
-            fut: None,
-            queue: Vec::new(),
-            inserting: Vec::new(),
-            insert_size,
-        }
+    pub fn new(client: clickhouse::Client, schema: ClickhouseSchema) -> Self {
+        info!(target: "uniV3", "created buffered clickhouse connection to {}", schema.clickhouse_database);
+        Self { client, schema }
     }
 
-    async fn insert(
-        db: Arc<ClickhouseClient<UniswapV3Tables>>,
-        vals: Vec<PoolData>,
-    ) -> eyre::Result<()> {
-        let (tick_info, slot0, trades) = PoolData::combine_many(vals);
-
-        if !tick_info.is_empty() {
-            db.insert_many::<UniV3TickInfo>(&tick_info).await?;
-        }
-
-        if !slot0.is_empty() {
-            db.insert_many::<UniV3Slot0>(&slot0).await?;
+    async fn insert_rows<T: Row + Serialize>(&self, table: &str, rows: &[T]) -> eyre::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
         }
 
-        if !trades.is_empty() {
-            db.insert_many::<UniV3Trades>(&trades).await?;
+        let mut insert = self
+            .client
+            .insert::<T>(&format!("{}.{table}", self.schema.clickhouse_database))?;
+        for row in rows {
+            insert.write(row).await?;
         }
+        insert.end().await?;
 
         Ok(())
     }
 }
 
-impl Future for BufferedClickhouse {
-    type Output = ();
+#[async_trait]
+impl PoolDataSink for BufferedClickhouse {
+    fn name(&self) -> &'static str {
+        "clickhouse"
+    }
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.get_mut();
+    async fn insert(&self, values: &[PoolData]) -> eyre::Result<()> {
+        let (tick_info, slot0, trades, candles) = PoolData::combine_many(values.to_vec());
 
-        let mut is_finished = false;
+        self.insert_rows("uni_v3_tick_info", &tick_info).await?;
+        self.insert_rows("uni_v3_slot0", &slot0).await?;
+        self.insert_rows("uni_v3_trades", &trades).await?;
+        self.insert_rows("uni_v3_candles", &candles).await?;
 
-        if let Poll::Ready(inc) = this.rx.poll_recv(cx) {
-            if let Some(vals) = inc {
-                this.queue.extend(vals);
-            } else if this.queue.is_empty() && this.inserting.is_empty() && this.fut.is_none() {
-                info!(target: "uniV3", "shutting down clickhouse connection");
-                return Poll::Ready(());
-            } else {
-                is_finished = true;
-            }
-        }
+        Ok(())
+    }
 
-        let fut = this.fut.take();
-        if let Some(mut f) = fut {
-            if let Poll::Ready(val) = f.poll_unpin(cx) {
-                if let Err(e) = val {
-                    let db = this.db.clone();
-                    this.fut = Some(Box::pin(Self::insert(db, this.inserting.clone())));
-                    error!(target: "uniV3::db", "error inserting into db, RETRYING - {:?}", e);
-                } else {
-                    info!(target: "uniV3::db", "inserted {} values into db", this.inserting.len());
-                    this.inserting.clear();
-                }
-            } else {
-                this.fut = Some(f)
-            }
-        } else if this.queue.len() >= this.insert_size || is_finished {
-            this.inserting = this.queue.drain(..).collect::<Vec<_>>();
-
-            let db = this.db.clone();
-            this.fut = Some(Box::pin(Self::insert(db, this.inserting.clone())));
+    async fn retract(&self, from_block: u64, pools: &[Address]) -> eyre::Result<()> {
+        if pools.is_empty() {
+            return Ok(());
         }
 
-        cx.waker().wake_by_ref();
+        for (table, block_column) in [
+            ("uni_v3_tick_info", "block_number"),
+            ("uni_v3_slot0", "block_number"),
+            ("uni_v3_trades", "block_number"),
+            ("uni_v3_candles", "start_block"),
+        ] {
+            let query = self.schema.retract_query(table, block_column, pools);
+            self.client.query(&query).bind(from_block).execute().await?;
+        }
 
-        Poll::Pending
+        Ok(())
     }
 }
@@ -0,0 +1,146 @@
+use alloy_primitives::{address, Address, BloomInput, B256};
+use alloy_sol_types::SolEvent;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use tracing::{info, warn};
+
+use crate::{
+    db::InitialPools,
+    execute_on_threadpool,
+    metadata::{PoolMetadata, PoolMetadataResolver},
+    node::EthNodeApi,
+    pools::UniswapV3Factory,
+};
+
+pub const UNISWAP_V3_FACTORY: Address = address!("1F98431c8aD98523631AE4a59f267346ea31F984");
+
+/// block the UniswapV3 factory was deployed in
+pub const UNISWAP_V3_FACTORY_DEPLOYMENT_BLOCK: u64 = 12369621;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredPool {
+    pub pool_address: Address,
+    pub token0: Address,
+    pub token1: Address,
+    pub fee: u32,
+    pub tick_spacing: i32,
+    pub creation_block: u64,
+}
+
+impl DiscoveredPool {
+    fn new(event: UniswapV3Factory::PoolCreated, creation_block: u64) -> Self {
+        Self {
+            pool_address: event.pool,
+            token0: event.token0,
+            token1: event.token1,
+            fee: event.fee,
+            tick_spacing: event.tickSpacing,
+            creation_block,
+        }
+    }
+}
+
+impl From<PoolMetadata> for DiscoveredPool {
+    fn from(value: PoolMetadata) -> Self {
+        Self {
+            pool_address: value.pool_address,
+            token0: value.token0,
+            token1: value.token1,
+            fee: value.fee,
+            tick_spacing: value.tick_spacing,
+            creation_block: value.creation_block,
+        }
+    }
+}
+
+/// scans the `PoolCreated` events of the UniswapV3 factory up to `end_block`, starting after
+/// the blocks already scanned by an earlier run, whose pools are kept in the metadata cache
+pub fn discover_pools(
+    node: &EthNodeApi,
+    resolver: &mut PoolMetadataResolver,
+    end_block: u64,
+) -> eyre::Result<Vec<DiscoveredPool>> {
+    let start_block = resolver.discovery_start();
+    if start_block <= end_block {
+        info!(target: "uniV3::discovery", "scanning factory events from block {start_block} - {end_block}");
+
+        let pools = execute_on_threadpool(|| {
+            (start_block..=end_block)
+                .into_par_iter()
+                .map(|block_number| pools_created_in_block(node, block_number))
+                .collect::<eyre::Result<Vec<_>>>()
+        })?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        info!(target: "uniV3::discovery", "discovered {} new pools", pools.len());
+        resolver.insert_discovered(pools, end_block);
+    }
+
+    let pools = resolver.discovered_pools(end_block);
+    info!(target: "uniV3::discovery", "{} pools created up to block {end_block}", pools.len());
+
+    Ok(pools)
+}
+
+/// resolves the token metadata of each discovered pool, skipping pools whose tokens fail to resolve
+pub fn discovered_to_initial_pools(
+    pools: Vec<DiscoveredPool>,
+    resolver: &mut PoolMetadataResolver,
+) -> Vec<InitialPools> {
+    let total = pools.len();
+    resolver.prefetch_tokens(pools.iter().flat_map(|pool| [pool.token0, pool.token1]));
+
+    let pools = pools
+        .into_iter()
+        .filter_map(|pool| {
+            let pool_address = pool.pool_address;
+            resolver.insert_pool(pool.into());
+
+            resolver
+                .initial_pool(pool_address)
+                .map_err(|e| {
+                    warn!(target: "uniV3::discovery", "failed to resolve tokens for pool {:?} - {:?}", pool_address, e)
+                })
+                .ok()
+        })
+        .collect::<Vec<_>>();
+
+    if pools.len() < total {
+        warn!(target: "uniV3::discovery", "skipped {} pools with unresolved tokens", total - pools.len());
+    }
+
+    pools
+}
+
+fn pools_created_in_block(
+    node: &EthNodeApi,
+    block_number: u64,
+) -> eyre::Result<Vec<DiscoveredPool>> {
+    let header = node.get_header(block_number)?;
+    if !bloom_contains_pool_created(&header.logs_bloom) {
+        return Ok(Vec::new());
+    }
+
+    let pools = node
+        .get_receipts(block_number)?
+        .into_iter()
+        .filter(|receipt| receipt.success)
+        .flat_map(|receipt| receipt.logs)
+        .filter(|log| log.address == UNISWAP_V3_FACTORY)
+        .filter_map(|log| {
+            UniswapV3Factory::PoolCreated::decode_log_data(&log.data, true)
+                .map(|event| DiscoveredPool::new(event, block_number))
+                .ok()
+        })
+        .collect();
+
+    Ok(pools)
+}
+
+fn bloom_contains_pool_created(bloom: &alloy_primitives::Bloom) -> bool {
+    let topic: B256 = UniswapV3Factory::PoolCreated::SIGNATURE_HASH;
+
+    bloom.contains_input(BloomInput::Raw(UNISWAP_V3_FACTORY.as_slice()))
+        && bloom.contains_input(BloomInput::Raw(topic.as_slice()))
+}
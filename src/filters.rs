@@ -0,0 +1,335 @@
+use std::collections::{HashMap, HashSet};
+
+use alloy_primitives::{Address, BloomInput};
+use alloy_sol_types::SolEvent;
+use clap::Args;
+use itertools::Itertools;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    execute_on_threadpool,
+    metadata::{PoolMetadataResolver, PoolMetadataSource},
+    node::EthNodeApi,
+    pools::{TrackedPool, UniswapV3},
+};
+
+/// ~1 day of blocks
+const DEFAULT_SWAP_LOOKBACK_BLOCKS: u64 = 7200;
+
+/// narrows the set of pools before any blocks are processed, empty filters keep every pool
+#[derive(Debug, Clone, Default, PartialEq, Args, Serialize, Deserialize)]
+#[command(next_help_heading = "Pool Filters")]
+#[serde(default)]
+pub struct PoolFilters {
+    /// only keep pools where either token matches one of the given addresses or symbols
+    #[arg(long = "filter-token", value_delimiter = ',')]
+    pub tokens: Vec<String>,
+
+    /// only keep pools with one of the given fee tiers (e.g. 500,3000)
+    #[arg(long = "filter-fee", value_delimiter = ',')]
+    pub fees: Vec<u32>,
+
+    /// minimum current `liquidity()` of the pool
+    #[arg(long)]
+    pub min_liquidity: Option<u128>,
+
+    /// minimum number of swaps in the pool over the lookback window
+    #[arg(long)]
+    pub min_swaps: Option<u64>,
+
+    /// number of blocks before the end block to count swaps over, defaults to 7200 (~1 day)
+    #[arg(long)]
+    pub swap_lookback_blocks: Option<u64>,
+}
+
+impl PoolFilters {
+    /// fills any filter that is unset in `self` from `other`
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            tokens: if self.tokens.is_empty() {
+                other.tokens
+            } else {
+                self.tokens
+            },
+            fees: if self.fees.is_empty() {
+                other.fees
+            } else {
+                self.fees
+            },
+            min_liquidity: self.min_liquidity.or(other.min_liquidity),
+            min_swaps: self.min_swaps.or(other.min_swaps),
+            swap_lookback_blocks: self.swap_lookback_blocks.or(other.swap_lookback_blocks),
+        }
+    }
+
+    /// keeps the pools matching every filter, logging a summary of the selected ones
+    pub fn apply(
+        &self,
+        pools: Vec<TrackedPool>,
+        node: &EthNodeApi,
+        resolver: &mut PoolMetadataResolver,
+        end_block: u64,
+    ) -> eyre::Result<Vec<TrackedPool>> {
+        let total = pools.len();
+        let mut selected = self.select(pools, resolver);
+
+        // counting swaps scans the receipts of every block in the window, so it runs last
+        if let Some(min_swaps) = self.min_swaps {
+            let lookback = self
+                .swap_lookback_blocks
+                .unwrap_or(DEFAULT_SWAP_LOOKBACK_BLOCKS);
+            let addresses = selected
+                .iter()
+                .map(|p| p.pool.pool_address)
+                .collect::<HashSet<_>>();
+            let swaps = count_swaps(
+                node,
+                &addresses,
+                end_block.saturating_sub(lookback),
+                end_block,
+            )?;
+
+            selected.retain(|p| {
+                swaps.get(&p.pool.pool_address).copied().unwrap_or(0) as u64 >= min_swaps
+            });
+        }
+
+        info!(target: "uniV3::filters", "selected {} of {} pools", selected.len(), total);
+        for pool in &selected {
+            let symbol = |address| {
+                resolver
+                    .cached_token(address)
+                    .map_or(format!("{:?}", address), |token| token.symbol)
+            };
+            debug!(target: "uniV3::filters", "{:?} - {}/{} created: {}, fetchers: {:?}", pool.pool.pool_address, symbol(pool.pool.token0_address), symbol(pool.pool.token1_address), pool.pool.creation_block, pool.fetchers);
+        }
+
+        Ok(selected)
+    }
+
+    /// the pools matching the token, fee and liquidity filters, only resolving the metadata
+    /// they need, a pool whose metadata fails to resolve is skipped
+    fn select(
+        &self,
+        pools: Vec<TrackedPool>,
+        metadata: &mut impl PoolMetadataSource,
+    ) -> Vec<TrackedPool> {
+        pools
+            .into_iter()
+            .filter(|pool| {
+                self.matches(pool, metadata)
+                    .map_err(|e| {
+                        warn!(target: "uniV3::filters", "skipping pool {:?}, failed to resolve its metadata - {:?}", pool.pool.pool_address, e)
+                    })
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+
+    fn matches(
+        &self,
+        pool: &TrackedPool,
+        metadata: &mut impl PoolMetadataSource,
+    ) -> eyre::Result<bool> {
+        let pool_address = pool.pool.pool_address;
+
+        if !self.tokens.is_empty() {
+            let token0 = metadata.token(pool.pool.token0_address)?;
+            let token1 = metadata.token(pool.pool.token1_address)?;
+
+            let matches_token = self.tokens.iter().any(|filter| {
+                [&token0, &token1]
+                    .iter()
+                    .any(|token| match filter.parse::<Address>() {
+                        Ok(address) => token.address == address,
+                        Err(_) => token.symbol.eq_ignore_ascii_case(filter),
+                    })
+            });
+            if !matches_token {
+                return Ok(false);
+            }
+        }
+
+        if !self.fees.is_empty() && !self.fees.contains(&metadata.fee(pool_address)?) {
+            return Ok(false);
+        }
+
+        if let Some(min_liquidity) = self.min_liquidity {
+            if metadata.liquidity(pool_address)? < min_liquidity {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+fn count_swaps(
+    node: &EthNodeApi,
+    pools: &HashSet<Address>,
+    start_block: u64,
+    end_block: u64,
+) -> eyre::Result<HashMap<Address, usize>> {
+    info!(target: "uniV3::filters", "counting swaps for {} pools in block range {start_block} - {end_block}", pools.len());
+
+    let topic = UniswapV3::Swap::SIGNATURE_HASH;
+
+    let swaps = execute_on_threadpool(|| {
+        (start_block..=end_block)
+            .into_par_iter()
+            .map(|block_number| {
+                let header = node.get_header(block_number)?;
+                if !header
+                    .logs_bloom
+                    .contains_input(BloomInput::Raw(topic.as_slice()))
+                {
+                    return Ok(Vec::new());
+                }
+
+                Ok(node
+                    .get_receipts(block_number)?
+                    .into_iter()
+                    .filter(|receipt| receipt.success)
+                    .flat_map(|receipt| receipt.logs)
+                    .filter(|log| {
+                        pools.contains(&log.address) && log.data.topics().first() == Some(&topic)
+                    })
+                    .map(|log| log.address)
+                    .collect::<Vec<_>>())
+            })
+            .collect::<eyre::Result<Vec<_>>>()
+    })?;
+
+    Ok(swaps.into_iter().flatten().counts())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::InitialPools, metadata::TokenMetadata, pools::FetcherKind};
+
+    #[derive(Default)]
+    struct MockMetadata {
+        tokens: HashMap<Address, TokenMetadata>,
+        fees: HashMap<Address, u32>,
+        liquidity: HashMap<Address, u128>,
+    }
+
+    impl PoolMetadataSource for MockMetadata {
+        fn token(&mut self, address: Address) -> eyre::Result<TokenMetadata> {
+            self.tokens
+                .get(&address)
+                .cloned()
+                .ok_or(eyre::ErrReport::msg("no token"))
+        }
+
+        fn fee(&mut self, pool_address: Address) -> eyre::Result<u32> {
+            self.fees
+                .get(&pool_address)
+                .copied()
+                .ok_or(eyre::ErrReport::msg("no fee"))
+        }
+
+        fn liquidity(&mut self, pool_address: Address) -> eyre::Result<u128> {
+            self.liquidity
+                .get(&pool_address)
+                .copied()
+                .ok_or(eyre::ErrReport::msg("no liquidity"))
+        }
+    }
+
+    fn token(address: u8, symbol: &str) -> TokenMetadata {
+        TokenMetadata {
+            address: Address::with_last_byte(address),
+            decimals: 18,
+            symbol: symbol.to_string(),
+            name: String::new(),
+        }
+    }
+
+    fn pool(pool: u8, token0: u8, token1: u8) -> TrackedPool {
+        TrackedPool::new(
+            InitialPools {
+                pool_address: Address::with_last_byte(pool),
+                token0_address: Address::with_last_byte(token0),
+                token0_decimals: 18,
+                token1_address: Address::with_last_byte(token1),
+                token1_decimals: 18,
+                creation_block: 12_400_000,
+            },
+            vec![FetcherKind::Slot0],
+        )
+    }
+
+    fn mock() -> MockMetadata {
+        let mut metadata = MockMetadata::default();
+        for token in [token(10, "WETH"), token(11, "USDC"), token(12, "PEPE")] {
+            metadata.tokens.insert(token.address, token);
+        }
+        metadata.fees = HashMap::from([
+            (Address::with_last_byte(1), 500),
+            (Address::with_last_byte(2), 3000),
+            (Address::with_last_byte(3), 10000),
+        ]);
+        metadata.liquidity = HashMap::from([
+            (Address::with_last_byte(1), 1_000),
+            (Address::with_last_byte(2), 10),
+        ]);
+
+        metadata
+    }
+
+    fn selected(filters: PoolFilters, metadata: &mut MockMetadata) -> Vec<u8> {
+        let pools = vec![pool(1, 10, 11), pool(2, 10, 12), pool(3, 11, 13)];
+        filters
+            .select(pools, metadata)
+            .iter()
+            .map(|pool| pool.pool.pool_address[19])
+            .collect()
+    }
+
+    #[test]
+    fn test_select_without_filters_resolves_nothing() {
+        let mut metadata = MockMetadata::default();
+        assert_eq!(
+            selected(PoolFilters::default(), &mut metadata),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_select_by_token() {
+        let filters = PoolFilters {
+            tokens: vec!["pepe".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(selected(filters, &mut mock()), vec![2]);
+
+        // pool 3's token1 doesn't resolve, so it's skipped instead of failing the selection
+        let filters = PoolFilters {
+            tokens: vec![format!("{:?}", Address::with_last_byte(11))],
+            ..Default::default()
+        };
+        assert_eq!(selected(filters, &mut mock()), vec![1]);
+    }
+
+    #[test]
+    fn test_select_by_fee() {
+        let filters = PoolFilters {
+            fees: vec![500, 10000],
+            ..Default::default()
+        };
+        assert_eq!(selected(filters, &mut mock()), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_select_by_liquidity() {
+        let filters = PoolFilters {
+            min_liquidity: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(selected(filters, &mut mock()), vec![1]);
+    }
+}
@@ -1,51 +1,225 @@
+use crate::backlog::PoolDataSender;
+use crate::concurrency::AdaptiveConcurrency;
+use crate::metrics;
 use crate::node::EthNodeApi;
-use crate::pools::PoolCaller;
+use crate::pools::{range_end, ChangeDetection, CompletedRange, FailedRange, PoolRangeCaller};
+use crate::progress::ProgressReporter;
+use alloy_primitives::B256;
 use futures::StreamExt;
 use futures::{stream::FuturesUnordered, Future};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tokio::runtime::Handle;
-use tokio::sync::mpsc::UnboundedSender;
 use tokio::task::JoinHandle;
-use tracing::error;
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{error, info, warn};
 
-use crate::pools::{types::PoolData, PoolFetcher};
+use crate::pools::{
+    types::{PoolBlockData, PoolUpdate},
+    PoolFetcher,
+};
+use crate::retry::{with_delay, DeadLetter, FailedStage, RetryPolicy};
 
 pub struct PoolHandler {
     pub node: Arc<EthNodeApi>,
-    pub db_tx: UnboundedSender<Vec<PoolData>>,
+    pub db_tx: PoolDataSender,
     pub pools: Vec<Arc<Box<dyn PoolFetcher>>>,
-    pub futs: FuturesUnordered<JoinHandle<Result<usize, (u64, eyre::ErrReport)>>>,
+    pub futs: FuturesUnordered<JoinHandle<Result<CompletedRange, FailedRange>>>,
+    pub start_block: u64,
     pub current_block: u64,
     pub end_block: u64,
     pub handle: Handle,
-    pub active_tasks: usize,
-    pub max_concurrent_tasks: usize,
+    pub in_flight_blocks: u64,
+    /// the number of blocks allowed in flight
+    pub concurrency: AdaptiveConcurrency,
+    pub skipped_blocks: u64,
+    pub change_detection: ChangeDetection,
+    /// number of consecutive blocks each task walks with carried state
+    pub range_size: u64,
+    /// keeps extending `end_block` once the range is complete
+    pub follow: Option<FollowTip>,
+    pub retry: RetryPolicy,
+    /// failed attempts of each block that is being retried
+    pub failed_attempts: HashMap<u64, u32>,
+    pub dead_letters: u64,
+    /// no new blocks are launched while the writer's backlog is over budget
+    pub paused: bool,
+    pub progress: ProgressReporter,
 }
 
 impl PoolHandler {
     pub fn new(
         node: Arc<EthNodeApi>,
-        db_tx: UnboundedSender<Vec<PoolData>>,
+        db_tx: PoolDataSender,
         pools: Vec<Arc<Box<dyn PoolFetcher>>>,
         start_block: u64,
         end_block: u64,
         handle: Handle,
-        max_concurrent_tasks: usize,
+        concurrency: AdaptiveConcurrency,
+        change_detection: ChangeDetection,
+        range_size: u64,
+        follow: Option<FollowTip>,
+        retry: RetryPolicy,
+        progress: ProgressReporter,
     ) -> Self {
         Self {
             node,
             db_tx,
             pools,
             futs: FuturesUnordered::new(),
+            start_block,
             current_block: start_block,
             end_block,
             handle,
-            active_tasks: 0,
-            max_concurrent_tasks,
+            in_flight_blocks: 0,
+            concurrency,
+            skipped_blocks: 0,
+            change_detection,
+            range_size: range_size.max(1),
+            follow,
+            retry,
+            failed_attempts: HashMap::new(),
+            dead_letters: 0,
+            paused: false,
+            progress,
         }
     }
+
+    fn spawn_range(&mut self, blocks: RangeInclusive<u64>, delay: Duration) {
+        let caller = PoolRangeCaller::new(
+            self.node.clone(),
+            self.db_tx.clone(),
+            &self.pools,
+            blocks,
+            self.change_detection,
+        );
+        self.futs
+            .push(self.handle.spawn(with_delay(delay, caller.execute_range())));
+    }
+
+    /// accounts for the blocks of a range that were sent to the writer, including the ones
+    /// completed before a failure
+    fn on_completed_range(&mut self, completed: CompletedRange) {
+        let blocks = completed.start_block..=completed.end_block;
+        self.in_flight_blocks -= completed.blocks();
+        self.progress.complete(blocks.clone());
+        metrics::record_blocks_processed(completed.blocks(), completed.skipped_blocks);
+        self.concurrency
+            .record_latency(completed.blocks(), completed.elapsed);
+        self.skipped_blocks += completed.skipped_blocks;
+        if !self.failed_attempts.is_empty() {
+            self.failed_attempts
+                .retain(|block, _| !blocks.contains(block));
+        }
+        if let Some(follow) = self.follow.as_mut() {
+            follow.record_blocks(completed.block_hashes);
+        }
+    }
+
+    /// retries the failed block with a backoff, or gives up on it and continues with the rest
+    /// of the range once it's out of attempts
+    fn on_failed_range(&mut self, blocks: RangeInclusive<u64>, e: eyre::ErrReport) {
+        let block_number = *blocks.start();
+        let attempts = self.failed_attempts.entry(block_number).or_default();
+        *attempts += 1;
+        let attempts = *attempts;
+
+        if self.retry.should_retry(attempts) {
+            let delay = self.retry.delay(attempts);
+            error!(target: "uniV3", "failed to get blocks {} - {} (attempt {}/{}), retrying in {:?} - {:?}", blocks.start(), blocks.end(), attempts, self.retry.max_attempts, delay, e);
+            self.progress.fetch_retries += 1;
+            metrics::record_retry(FailedStage::Fetch);
+            self.spawn_range(blocks, delay);
+            return;
+        }
+
+        self.failed_attempts.remove(&block_number);
+        self.retry.record(DeadLetter::new(
+            FailedStage::Fetch,
+            vec![block_number],
+            attempts,
+            &e,
+        ));
+        self.dead_letters += 1;
+        self.in_flight_blocks -= 1;
+        self.progress.give_up(block_number);
+
+        // the writer still needs the block to move its watermarks past it
+        match self.node.get_header(block_number) {
+            Ok(header) => {
+                let _ = self
+                    .db_tx
+                    .send(PoolBlockData::failed(block_number, header.timestamp).into());
+            }
+            Err(e) => {
+                error!(target: "uniV3", "failed to get header of dead letter block {block_number} - {:?}", e)
+            }
+        }
+
+        if block_number < *blocks.end() {
+            self.spawn_range(block_number + 1..=*blocks.end(), Duration::ZERO);
+        }
+    }
+}
+
+/// number of the most recently processed blocks checked for reorgs
+pub(crate) const REORG_WINDOW: usize = 128;
+
+/// polls the reth db for new canonical blocks once the block range is complete
+pub struct FollowTip {
+    /// blocks are only processed once they are this many blocks behind the tip
+    pub confirmations: u64,
+    pub interval: Interval,
+    /// the hash each recently processed block had when it was processed
+    pub block_hashes: BTreeMap<u64, B256>,
+}
+
+impl FollowTip {
+    pub fn new(confirmations: u64, poll_interval: Duration) -> Self {
+        let mut interval = tokio::time::interval(poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Self {
+            confirmations,
+            interval,
+            block_hashes: BTreeMap::new(),
+        }
+    }
+
+    pub fn record_blocks(&mut self, block_hashes: Vec<(u64, B256)>) {
+        self.block_hashes.extend(block_hashes);
+        while self.block_hashes.len() > REORG_WINDOW {
+            self.block_hashes.pop_first();
+        }
+    }
+
+    /// the confirmed tip on each tick of the interval
+    fn poll_tip(&mut self, cx: &mut Context<'_>, node: &EthNodeApi) -> Poll<eyre::Result<u64>> {
+        if self.interval.poll_tick(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        Poll::Ready(Ok(node
+            .get_current_block()?
+            .saturating_sub(self.confirmations)))
+    }
+
+    /// the first processed block whose canonical hash changed, forgetting it and every later block
+    fn find_reorg(&mut self, node: &EthNodeApi) -> eyre::Result<Option<u64>> {
+        for (block_number, block_hash) in &self.block_hashes {
+            if node.get_block_hash(*block_number)? != Some(*block_hash) {
+                let block_number = *block_number;
+                self.block_hashes.split_off(&block_number);
+                return Ok(Some(block_number));
+            }
+        }
+
+        Ok(None)
+    }
 }
 
 impl Future for PoolHandler {
@@ -59,35 +233,87 @@ impl Future for PoolHandler {
         loop {
             while let Poll::Ready(Some(val)) = this.futs.poll_next_unpin(cx) {
                 match val {
-                    Ok(Ok(t)) => this.active_tasks -= t,
-                    Ok(Err((b, e))) => {
-                        error!(target: "uniV3", "failed to get block {b}, retrying - {:?}", e);
-                        let caller =
-                            PoolCaller::new(this.node.clone(), this.db_tx.clone(), &this.pools, b);
-                        this.futs
-                            .push(this.handle.clone().spawn(caller.execute_block()));
+                    Ok(Ok(completed)) => this.on_completed_range(completed),
+                    Ok(Err(failed)) => {
+                        if let Some(completed) = failed.completed {
+                            this.on_completed_range(completed);
+                        }
+                        this.on_failed_range(failed.blocks, failed.error);
                     }
                     _ => (),
                 }
             }
 
+            this.concurrency.poll_adjust(cx, this.in_flight_blocks);
+
+            this.progress.poll_report(
+                cx,
+                this.in_flight_blocks,
+                this.end_block,
+                &this.db_tx.rows,
+                &this.db_tx.writes,
+            );
+
+            let backlog_full = this.db_tx.backlog.is_full();
+            if backlog_full != this.paused {
+                this.paused = backlog_full;
+                let backlog = &this.db_tx.backlog;
+                if backlog_full {
+                    info!(target: "uniV3", "pausing new blocks until the writer catches up, {} rows ({} bytes) waiting to be written", backlog.rows(), backlog.bytes());
+                } else {
+                    info!(target: "uniV3", "resuming new blocks, {} rows ({} bytes) waiting to be written", backlog.rows(), backlog.bytes());
+                }
+            }
+
             if this.end_block >= this.current_block
-                && this.active_tasks <= this.max_concurrent_tasks
+                && this.concurrency.has_capacity(this.in_flight_blocks)
+                && !backlog_full
             {
-                let caller = PoolCaller::new(
-                    this.node.clone(),
-                    this.db_tx.clone(),
-                    &this.pools,
-                    this.current_block,
-                );
-                this.active_tasks += caller.pools.len();
-                this.futs
-                    .push(this.handle.clone().spawn(caller.execute_block()));
-                this.current_block += 1;
+                let range_end = range_end(this.current_block, this.range_size, this.end_block);
+                this.spawn_range(this.current_block..=range_end, Duration::ZERO);
+                this.in_flight_blocks += range_end - this.current_block + 1;
+                this.current_block = range_end + 1;
             }
 
             if this.futs.is_empty() && this.end_block < this.current_block {
-                return Poll::Ready(());
+                let Some(follow) = this.follow.as_mut() else {
+                    info!(target: "uniV3", "completed block range {} - {}, skipped {} blocks with no logs from the tracked pools, gave up on {} blocks", this.start_block, this.end_block, this.skipped_blocks, this.dead_letters);
+                    return Poll::Ready(());
+                };
+
+                // the interval wakes the handler, so there is no need to spin while waiting
+                match follow.poll_tip(cx, &this.node) {
+                    Poll::Ready(Ok(confirmed)) => {
+                        match follow.find_reorg(&this.node) {
+                            Ok(Some(reorg_block)) => {
+                                warn!(target: "uniV3", "block {reorg_block} was reorged, retracting and re-processing blocks {reorg_block} - {}", this.end_block);
+                                if let Err(e) = this.db_tx.send(PoolUpdate::Reorg {
+                                    from_block: reorg_block,
+                                }) {
+                                    error!(target: "uniV3", "failed to send the retraction of block {reorg_block} - {:?}", e);
+                                }
+                                this.current_block = reorg_block;
+                                this.progress.rewind(reorg_block);
+                                this.end_block = this.end_block.min(confirmed);
+                            }
+                            Ok(None) => (),
+                            Err(e) => {
+                                error!(target: "uniV3", "failed to check for reorgs - {:?}", e)
+                            }
+                        }
+
+                        if confirmed > this.end_block {
+                            info!(target: "uniV3", "following new blocks {} - {}", this.end_block + 1, confirmed);
+                            this.end_block = confirmed;
+                        }
+                        continue;
+                    }
+                    Poll::Ready(Err(e)) => {
+                        error!(target: "uniV3", "failed to get the chain tip - {:?}", e);
+                        continue;
+                    }
+                    Poll::Pending => return Poll::Pending,
+                }
             }
 
             work -= 1;
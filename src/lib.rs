@@ -1,11 +1,26 @@
+use backlog::{pool_data_channel, WriteBacklog};
+use candles::CandleAggregator;
+use checkpoint::{Checkpoint, CheckpointTracker};
 use clap::Parser;
-use cli::CliCmd;
+use cli::{CliCmd, Command};
+use concurrency::AdaptiveConcurrency;
 use db::{get_initial_pools, spawn_clickhouse_db};
+use discovery::{discover_pools, discovered_to_initial_pools};
+use itertools::Itertools;
+use manifest::PoolManifest;
+use metadata::PoolMetadataResolver;
 use node::EthNodeApi;
-use pools::{PoolFetcher, PoolSlot0Fetcher, PoolTickFetcher, PoolTradeFetcher};
-use std::sync::Arc;
-use tokio::sync::mpsc::unbounded_channel;
-use tracing::info;
+use pools::{FetcherKind, TrackedPool};
+use progress::ProgressReporter;
+use sinks::{
+    parquet::ParquetSink,
+    sqlite::SqliteSink,
+    tcp::TcpSink,
+    text::{CsvSink, JsonlSink},
+    PoolDataSink, SinkKind,
+};
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
+use tracing::{info, warn};
 use utils::TokenInfo;
 
 mod handler;
@@ -14,11 +29,26 @@ pub use handler::*;
 mod runner;
 pub use runner::*;
 
-use crate::db::BufferedClickhouse;
+use crate::{db::BufferedClickhouse, writer::BufferedWriter};
 
 mod aux;
 pub use aux::{execute_on_threadpool, init_all};
+pub mod backlog;
+pub mod candles;
+pub mod checkpoint;
+pub mod concurrency;
 pub mod db;
+pub mod discovery;
+pub mod filters;
+pub mod manifest;
+pub mod metadata;
+pub mod metrics;
+pub mod progress;
+pub mod query;
+pub mod retry;
+pub mod schema;
+pub mod sinks;
+pub mod writer;
 
 mod cli;
 
@@ -36,60 +66,152 @@ pub fn run() -> eyre::Result<()> {
 
 async fn execute(executor: TaskExecutor) -> eyre::Result<()> {
     let cli = CliCmd::parse();
-    aux::init_all(cli.verbosity.directive());
+    match &cli.command {
+        Some(Command::Query(query)) => return execute_query(executor, &cli, query).await,
+        Some(Command::Schema(cmd)) => {
+            aux::init_all(cli.verbosity.directive(), false);
+            let db = spawn_clickhouse_db();
+            return if cmd.check {
+                schema::verify(&db, &cli.clickhouse).await
+            } else {
+                schema::apply(&db, &cli.clickhouse).await
+            };
+        }
+        None => {}
+    }
+
+    // keeps the logs out of json lines piped from stdout
+    let jsonl_to_stdout =
+        cli.sinks.contains(&SinkKind::Jsonl) && cli.text.jsonl_output == Path::new("-");
+    aux::init_all(cli.verbosity.directive(), jsonl_to_stdout);
+    if let Some(addr) = cli.metrics {
+        metrics::install_exporter(addr)?;
+    }
 
     let reth_db_path = std::env::var("RETH_DB_PATH").expect("no 'RETH_DB_PATH' in .env");
     let node = Arc::new(EthNodeApi::new(&reth_db_path, executor.handle().clone())?);
     let current_block = node.get_current_block()?;
 
-    let db = Arc::new(spawn_clickhouse_db());
+    // only connect when the pools are read from or the values written to clickhouse
+    let db = (cli.sinks.contains(&SinkKind::Clickhouse) || (cli.pools.is_none() && !cli.discover))
+        .then(spawn_clickhouse_db);
 
-    let (tx, rx) = unbounded_channel();
-    let buffered_db = BufferedClickhouse::new(db.clone(), rx, cli.insert_size);
-    executor.spawn_blocking(buffered_db);
+    let end_block = cli.end_block.unwrap_or(if cli.follow {
+        current_block.saturating_sub(cli.confirmations)
+    } else {
+        current_block
+    });
 
-    let (min_block, pools) = get_initial_pools(&db).await?;
+    let mut resolver =
+        PoolMetadataResolver::new(node.clone(), current_block, &cli.metadata_cache).await?;
+    let pools = load_pools(&cli, &node, db.as_ref(), &mut resolver, end_block).await?;
+    resolver.save()?;
 
-    let mut pool_fetchers = Vec::new();
-    if cli.slot0 {
-        info!(target: "uniV3::slot0", "enabled slot0 fetcher");
-        let slot0_pools = pools.iter().map(|pool| {
-            Arc::new(Box::new(PoolSlot0Fetcher::new(
-                pool.pool_address,
-                TokenInfo::new(pool.token0_address, pool.token0_decimals),
-                TokenInfo::new(pool.token1_address, pool.token1_decimals),
-                pool.creation_block,
-            )) as Box<dyn PoolFetcher>)
-        });
-        pool_fetchers.extend(slot0_pools)
+    // existing checkpoints of pools outside this run are kept when it is saved
+    let saved_checkpoint = Checkpoint::load(&cli.checkpoint)?;
+    let resume_from = if cli.resume {
+        saved_checkpoint.clone()
+    } else {
+        Checkpoint::default()
+    };
+
+    let fetcher_starts = pools
+        .iter()
+        .flat_map(|pool| {
+            pool.fetchers.iter().map(|kind| {
+                (
+                    pool.pool.pool_address,
+                    *kind,
+                    resume_from.start_block(pool, *kind),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let min_block = fetcher_starts
+        .iter()
+        .map(|(_, _, start_block)| *start_block)
+        .min()
+        .unwrap_or(end_block);
+    let start_block = cli.start_block.unwrap_or(min_block);
+    if cli.resume {
+        info!(target: "uniV3::checkpoint", "resuming from block {start_block} with the checkpoint in {}", cli.checkpoint.display());
     }
 
-    if cli.tick_info {
-        info!(target: "uniV3::tick-info", "enabled tick-info fetcher");
-        let tick_info_pools = pools.iter().map(|pool| {
-            Arc::new(
-                Box::new(PoolTickFetcher::new(pool.pool_address, pool.creation_block))
-                    as Box<dyn PoolFetcher>,
-            )
+    // a fetcher starting past the block after its checkpoint keeps it, so a later `--resume`
+    // still fills the blocks in between
+    let pools_by_address = pools
+        .iter()
+        .map(|pool| (pool.pool.pool_address, pool))
+        .collect::<HashMap<_, _>>();
+    let (tracked, gaps): (Vec<_>, Vec<_>) = fetcher_starts
+        .into_iter()
+        .map(|(pool, kind, fetcher_start)| (pool, kind, fetcher_start.max(start_block)))
+        .partition(|(pool, kind, fetcher_start)| {
+            saved_checkpoint.continues(pools_by_address[pool], *kind, *fetcher_start)
         });
-        pool_fetchers.extend(tick_info_pools)
+    if !gaps.is_empty() {
+        warn!(target: "uniV3::checkpoint", "not checkpointing {} fetchers that start past their checkpoint, run with --resume to fill the blocks before block {start_block}", gaps.len());
     }
 
-    if cli.trades {
-        info!(target: "uniV3::trades", "enabled trades fetcher");
-        let trade_pools = pools.iter().map(|pool| {
-            Arc::new(Box::new(PoolTradeFetcher::new(
-                pool.pool_address,
-                TokenInfo::new(pool.token0_address, pool.token0_decimals),
-                TokenInfo::new(pool.token1_address, pool.token1_decimals),
-                pool.creation_block,
-            )) as Box<dyn PoolFetcher>)
-        });
-        pool_fetchers.extend(trade_pools)
+    let checkpoint = CheckpointTracker::new(
+        cli.checkpoint.clone(),
+        saved_checkpoint,
+        tracked,
+        start_block,
+    );
+
+    let candles = (!cli.candles.is_empty()).then(|| {
+        info!(target: "uniV3::candles", "enabled candle aggregation for intervals {:?}", cli.candles);
+        let pool_tokens = pools
+            .iter()
+            .map(|TrackedPool { pool, .. }| {
+                (
+                    pool.pool_address,
+                    (
+                        TokenInfo::new(pool.token0_address, pool.token0_decimals),
+                        TokenInfo::new(pool.token1_address, pool.token1_decimals),
+                    ),
+                )
+            })
+            .collect();
+        CandleAggregator::new(cli.candles.clone(), pool_tokens, start_block)
+    });
+
+    let (tx, rx) = pool_data_channel(WriteBacklog::new(cli.max_queued_rows, cli.max_queued_bytes));
+    let writer = BufferedWriter::new(
+        build_sinks(&cli, db.as_ref()).await?,
+        pools.iter().map(|p| p.pool.pool_address).collect(),
+        rx,
+        cli.insert_size,
+        candles,
+        Some(checkpoint),
+        cli.retry.clone(),
+    );
+    // near the tip blocks arrive slower than the buffer fills
+    let writer = if cli.follow {
+        writer.with_flush_interval(Duration::from_secs(cli.poll_interval))
+    } else {
+        writer
+    };
+    executor.spawn_blocking(writer);
+
+    for kind in [
+        FetcherKind::Slot0,
+        FetcherKind::TickInfo,
+        FetcherKind::Trades,
+    ] {
+        let enabled = pools.iter().filter(|p| p.fetchers.contains(&kind)).count();
+        if enabled > 0 {
+            info!(target: "uniV3", "enabled {:?} fetcher for {enabled} pools", kind);
+        }
     }
 
-    let start_block = cli.start_block.unwrap_or(min_block);
-    let end_block = cli.end_block.unwrap_or(current_block);
+    let pool_fetchers = pools
+        .iter()
+        .flat_map(|pool| pool.build_fetchers(&resume_from))
+        .collect::<Vec<_>>();
+
+    info!(target: "uniV3", "detecting pool changes from {:?}", cli.change_detection);
     info!(target: "uniV3", "starting block range {start_block} - {end_block} for {} pools", pools.len());
 
     let handler = PoolHandler::new(
@@ -99,7 +221,13 @@ async fn execute(executor: TaskExecutor) -> eyre::Result<()> {
         start_block,
         end_block,
         executor.handle().clone(),
-        cli.max_concurrent_tasks,
+        AdaptiveConcurrency::new(cli.concurrency.clone()),
+        cli.change_detection,
+        cli.range_size,
+        cli.follow
+            .then(|| FollowTip::new(cli.confirmations, Duration::from_secs(cli.poll_interval))),
+        cli.retry.clone(),
+        ProgressReporter::new(start_block, Duration::from_secs(cli.progress_interval)),
     );
 
     executor
@@ -108,3 +236,81 @@ async fn execute(executor: TaskExecutor) -> eyre::Result<()> {
 
     Ok(())
 }
+
+/// prints the pool's state as json on stdout, so the logs go to stderr
+async fn execute_query(
+    executor: TaskExecutor,
+    cli: &CliCmd,
+    query: &query::PoolStateQuery,
+) -> eyre::Result<()> {
+    aux::init_all(cli.verbosity.directive(), true);
+
+    let reth_db_path = std::env::var("RETH_DB_PATH").expect("no 'RETH_DB_PATH' in .env");
+    let node = Arc::new(EthNodeApi::new(&reth_db_path, executor.handle().clone())?);
+    let state = query::query_pool_state(node, query).await?;
+    println!("{}", serde_json::to_string_pretty(&state)?);
+
+    Ok(())
+}
+
+async fn build_sinks(
+    cli: &CliCmd,
+    db: Option<&clickhouse::Client>,
+) -> eyre::Result<Vec<Arc<dyn PoolDataSink>>> {
+    let mut sinks = Vec::new();
+    for kind in cli.sinks.iter().unique() {
+        let sink: Arc<dyn PoolDataSink> = match kind {
+            SinkKind::Clickhouse => {
+                let db = db.ok_or(eyre::ErrReport::msg(
+                    "the clickhouse sink needs a clickhouse connection",
+                ))?;
+                schema::verify(db, &cli.clickhouse).await?;
+                Arc::new(BufferedClickhouse::new(db.clone(), cli.clickhouse.clone()))
+            }
+            SinkKind::Parquet => Arc::new(ParquetSink::new(cli.parquet.clone())?),
+            SinkKind::Jsonl => Arc::new(JsonlSink::new(&cli.text.jsonl_output)?),
+            SinkKind::Csv => Arc::new(CsvSink::new(cli.text.csv_dir.clone())?),
+            SinkKind::Sqlite => Arc::new(SqliteSink::new(cli.sqlite.clone())?),
+            SinkKind::Tcp => Arc::new(TcpSink::bind(cli.tcp.clone()).await?),
+        };
+        sinks.push(sink);
+    }
+
+    Ok(sinks)
+}
+
+async fn load_pools(
+    cli: &CliCmd,
+    node: &EthNodeApi,
+    db: Option<&clickhouse::Client>,
+    resolver: &mut PoolMetadataResolver,
+    end_block: u64,
+) -> eyre::Result<Vec<TrackedPool>> {
+    let default_fetchers = cli.fetchers();
+
+    let (pools, filters) = if let Some(manifest_path) = &cli.pools {
+        let manifest = PoolManifest::load(manifest_path)?;
+        let filters = cli.filters.clone().or(manifest.filters.clone());
+
+        (manifest.resolve(resolver, &default_fetchers)?, filters)
+    } else {
+        let pools = if cli.discover {
+            let discovered = discover_pools(node, resolver, end_block)?;
+            discovered_to_initial_pools(discovered, resolver)
+        } else {
+            let db = db.ok_or(eyre::ErrReport::msg(
+                "the predefined pool list is read from clickhouse",
+            ))?;
+            get_initial_pools(db, resolver).await?
+        };
+
+        let pools = pools
+            .into_iter()
+            .map(|pool| TrackedPool::new(pool, default_fetchers.clone()))
+            .collect::<Vec<_>>();
+
+        (pools, cli.filters.clone())
+    };
+
+    filters.apply(pools, node, resolver, end_block)
+}
@@ -0,0 +1,172 @@
+use std::{collections::HashMap, path::Path};
+
+use alloy_primitives::Address;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    db::InitialPools,
+    filters::PoolFilters,
+    metadata::PoolMetadataResolver,
+    pools::{FetcherKind, TrackedPool},
+    utils::TokenInfo,
+};
+
+/// list of pools to track, read from a `.toml` or `.json` file
+///
+/// ```toml
+/// [[pools]]
+/// address = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640"
+/// creation_block = 12376729
+/// fetchers = ["slot0", "trades"]
+/// token0 = { address = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", decimals = 6 }
+/// token1 = { address = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", decimals = 18 }
+///
+/// [filters]
+/// fees = [500, 3000]
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PoolManifest {
+    pub pools: Vec<ManifestPool>,
+    /// applied to the pools unless overridden from the cli
+    #[serde(default)]
+    pub filters: PoolFilters,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ManifestPool {
+    pub address: Address,
+    pub token0: Option<TokenInfo>,
+    pub token1: Option<TokenInfo>,
+    pub creation_block: Option<u64>,
+    /// defaults to the fetchers enabled from the cli
+    #[serde(default)]
+    pub fetchers: Vec<FetcherKind>,
+}
+
+impl ManifestPool {
+    fn to_initial_pool(&self, fallback: Option<&InitialPools>) -> Option<InitialPools> {
+        let token0 = self
+            .token0
+            .clone()
+            .or_else(|| fallback.map(|p| TokenInfo::new(p.token0_address, p.token0_decimals)))?;
+        let token1 = self
+            .token1
+            .clone()
+            .or_else(|| fallback.map(|p| TokenInfo::new(p.token1_address, p.token1_decimals)))?;
+        let creation_block = self
+            .creation_block
+            .or_else(|| fallback.map(|p| p.creation_block))?;
+
+        Some(InitialPools {
+            pool_address: self.address,
+            token0_address: token0.address,
+            token0_decimals: token0.decimals,
+            token1_address: token1.address,
+            token1_decimals: token1.decimals,
+            creation_block,
+        })
+    }
+}
+
+impl PoolManifest {
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(toml::from_str(&contents)?),
+            Some("json") => Ok(serde_json::from_str(&contents)?),
+            _ => Err(eyre::ErrReport::msg(format!(
+                "unsupported pool manifest format: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// fills in any missing token metadata or creation blocks from the chain
+    pub fn resolve(
+        self,
+        resolver: &mut PoolMetadataResolver,
+        default_fetchers: &[FetcherKind],
+    ) -> eyre::Result<Vec<TrackedPool>> {
+        let mut fallbacks = HashMap::new();
+        for pool in &self.pools {
+            if pool.to_initial_pool(None).is_none() {
+                fallbacks.insert(pool.address, resolver.initial_pool(pool.address)?);
+            }
+        }
+
+        if !fallbacks.is_empty() {
+            info!(target: "uniV3::manifest", "resolved metadata for {} pools", fallbacks.len());
+        }
+
+        self.pools
+            .into_iter()
+            .map(|pool| {
+                let initial_pool = pool.to_initial_pool(fallbacks.get(&pool.address)).ok_or(
+                    eyre::ErrReport::msg(format!("no metadata found for pool {:?}", pool.address)),
+                )?;
+                let fetchers = if pool.fetchers.is_empty() {
+                    default_fetchers.to_vec()
+                } else {
+                    pool.fetchers
+                };
+
+                Ok(TrackedPool::new(initial_pool, fetchers))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_toml_and_json_manifests() {
+        let toml_manifest = r#"
+            [[pools]]
+            address = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640"
+            creation_block = 12376729
+            fetchers = ["slot0", "tick_info"]
+            token0 = { address = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", decimals = 6 }
+            token1 = { address = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", decimals = 18 }
+
+            [[pools]]
+            address = "0x4e68ccd3e89f51c3074ca5072bbac773960dfa36"
+        "#;
+        let json_manifest = r#"{"pools": [
+            {
+                "address": "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640",
+                "creation_block": 12376729,
+                "fetchers": ["slot0", "tick_info"],
+                "token0": {"address": "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", "decimals": 6},
+                "token1": {"address": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", "decimals": 18}
+            },
+            {"address": "0x4e68ccd3e89f51c3074ca5072bbac773960dfa36"}
+        ]}"#;
+
+        let from_toml: PoolManifest = toml::from_str(toml_manifest).unwrap();
+        let from_json: PoolManifest = serde_json::from_str(json_manifest).unwrap();
+        assert_eq!(from_toml, from_json);
+
+        let expected = InitialPools {
+            pool_address: Address::from_str("0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640").unwrap(),
+            token0_address: Address::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48")
+                .unwrap(),
+            token0_decimals: 6,
+            token1_address: Address::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2")
+                .unwrap(),
+            token1_decimals: 18,
+            creation_block: 12376729,
+        };
+        assert_eq!(from_toml.pools[0].to_initial_pool(None), Some(expected));
+        assert_eq!(
+            from_toml.pools[0].fetchers,
+            vec![FetcherKind::Slot0, FetcherKind::TickInfo]
+        );
+        assert_eq!(from_toml.pools[1].to_initial_pool(None), None);
+    }
+}
@@ -0,0 +1,430 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use alloy_primitives::{Address, B256};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    db::InitialPools,
+    discovery::{DiscoveredPool, UNISWAP_V3_FACTORY_DEPLOYMENT_BLOCK},
+    execute_on_threadpool,
+    node::EthNodeApi,
+    pools::PoolDBInner,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenMetadata {
+    pub address: Address,
+    pub decimals: u8,
+    pub symbol: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PoolMetadata {
+    pub pool_address: Address,
+    pub token0: Address,
+    pub token1: Address,
+    pub fee: u32,
+    pub tick_spacing: i32,
+    pub creation_block: u64,
+}
+
+impl From<DiscoveredPool> for PoolMetadata {
+    fn from(value: DiscoveredPool) -> Self {
+        Self {
+            pool_address: value.pool_address,
+            token0: value.token0,
+            token1: value.token1,
+            fee: value.fee,
+            tick_spacing: value.tick_spacing,
+            creation_block: value.creation_block,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MetadataCache {
+    pools: HashMap<Address, PoolMetadata>,
+    tokens: HashMap<Address, TokenMetadata>,
+    #[serde(default)]
+    discovery: DiscoveryCache,
+}
+
+/// the pools found by `--discover` and the last block scanned for them
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+struct DiscoveryCache {
+    scanned_through: Option<u64>,
+    pools: Vec<Address>,
+}
+
+impl MetadataCache {
+    fn load(path: &Path) -> eyre::Result<Self> {
+        if path.exists() {
+            Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    fn save(&self, path: &Path) -> eyre::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn discovery_start(&self) -> u64 {
+        self.discovery
+            .scanned_through
+            .map_or(UNISWAP_V3_FACTORY_DEPLOYMENT_BLOCK, |block| block + 1)
+    }
+
+    fn insert_discovered(&mut self, pools: Vec<DiscoveredPool>, scanned_through: u64) {
+        let mut known = self.discovery.pools.iter().copied().collect::<HashSet<_>>();
+        for pool in pools {
+            if known.insert(pool.pool_address) {
+                self.discovery.pools.push(pool.pool_address);
+            }
+            self.pools.insert(pool.pool_address, pool.into());
+        }
+        self.discovery.scanned_through = Some(
+            self.discovery
+                .scanned_through
+                .map_or(scanned_through, |block| block.max(scanned_through)),
+        );
+    }
+
+    fn discovered_pools(&self, end_block: u64) -> Vec<DiscoveredPool> {
+        let mut pools = self
+            .discovery
+            .pools
+            .iter()
+            .filter_map(|address| self.pools.get(address))
+            .filter(|pool| pool.creation_block <= end_block)
+            .cloned()
+            .map(DiscoveredPool::from)
+            .collect::<Vec<_>>();
+        pools.sort_by_key(|pool| pool.creation_block);
+
+        pools
+    }
+}
+
+/// the metadata the pool filters are evaluated on
+pub trait PoolMetadataSource {
+    fn token(&mut self, address: Address) -> eyre::Result<TokenMetadata>;
+
+    fn fee(&mut self, pool_address: Address) -> eyre::Result<u32>;
+
+    fn liquidity(&mut self, pool_address: Address) -> eyre::Result<u128>;
+}
+
+/// resolves pool and token metadata with calls against the reth db, caching the results
+/// in a local file so only new pools and tokens are called on later runs
+pub struct PoolMetadataResolver {
+    pub node: Arc<EthNodeApi>,
+    pub block_number: u64,
+    inner: PoolDBInner,
+    cache: MetadataCache,
+    cache_path: PathBuf,
+}
+
+impl PoolMetadataResolver {
+    pub async fn new(
+        node: Arc<EthNodeApi>,
+        block_number: u64,
+        cache_path: &Path,
+    ) -> eyre::Result<Self> {
+        let cache = MetadataCache::load(cache_path)?;
+        info!(target: "uniV3::metadata", "loaded {} pools and {} tokens from the metadata cache", cache.pools.len(), cache.tokens.len());
+
+        Ok(Self {
+            inner: PoolDBInner::new(node.clone(), block_number).await?,
+            node,
+            block_number,
+            cache,
+            cache_path: cache_path.to_path_buf(),
+        })
+    }
+
+    pub fn save(&self) -> eyre::Result<()> {
+        self.cache.save(&self.cache_path)
+    }
+
+    pub fn pool(&mut self, pool_address: Address) -> eyre::Result<PoolMetadata> {
+        if let Some(pool) = self.cache.pools.get(&pool_address) {
+            return Ok(pool.clone());
+        }
+
+        let (token0, token1) = self.inner.get_pool_tokens(pool_address)?;
+        let creation_block = self
+            .node
+            .get_contract_creation_block(
+                pool_address,
+                UNISWAP_V3_FACTORY_DEPLOYMENT_BLOCK,
+                self.block_number,
+            )?
+            .ok_or(eyre::ErrReport::msg(format!(
+                "no code found for pool {:?}",
+                pool_address
+            )))?;
+
+        let pool = PoolMetadata {
+            pool_address,
+            token0,
+            token1,
+            fee: self.inner.get_fee(pool_address)?,
+            tick_spacing: self.inner.get_tick_spacing(pool_address)?,
+            creation_block,
+        };
+        self.cache.pools.insert(pool_address, pool.clone());
+
+        Ok(pool)
+    }
+
+    pub fn token(&mut self, address: Address) -> eyre::Result<TokenMetadata> {
+        if let Some(token) = self.cache.tokens.get(&address) {
+            return Ok(token.clone());
+        }
+
+        let token = fetch_token(&mut self.inner, address)?;
+        self.cache.tokens.insert(address, token.clone());
+
+        Ok(token)
+    }
+
+    /// resolves the tokens missing from the cache in parallel, the ones that fail are left out
+    /// so resolving them again with `token` reports the error
+    pub fn prefetch_tokens(&mut self, addresses: impl IntoIterator<Item = Address>) {
+        let missing = addresses
+            .into_iter()
+            .filter(|address| !self.cache.tokens.contains_key(address))
+            .collect::<HashSet<_>>();
+        if missing.is_empty() {
+            return;
+        }
+        info!(target: "uniV3::metadata", "resolving the metadata of {} tokens", missing.len());
+
+        let inner = &self.inner;
+        let tokens = execute_on_threadpool(|| {
+            missing
+                .into_par_iter()
+                .map_init(
+                    || inner.clone(),
+                    |inner, address| {
+                        fetch_token(inner, address)
+                            .map_err(|e| {
+                                debug!(target: "uniV3::metadata", "failed to resolve token {:?} - {:?}", address, e)
+                            })
+                            .ok()
+                    },
+                )
+                .collect::<Vec<_>>()
+        });
+
+        self.cache.tokens.extend(
+            tokens
+                .into_iter()
+                .flatten()
+                .map(|token| (token.address, token)),
+        );
+    }
+
+    /// first block that hasn't been scanned for `PoolCreated` events by an earlier run
+    pub fn discovery_start(&self) -> u64 {
+        self.cache.discovery_start()
+    }
+
+    /// caches the pools created in the blocks scanned up to `scanned_through`
+    pub fn insert_discovered(&mut self, pools: Vec<DiscoveredPool>, scanned_through: u64) {
+        self.cache.insert_discovered(pools, scanned_through)
+    }
+
+    /// every discovered pool created up to `end_block`, oldest first
+    pub fn discovered_pools(&self, end_block: u64) -> Vec<DiscoveredPool> {
+        self.cache.discovered_pools(end_block)
+    }
+
+    /// the token's metadata if an earlier call or run resolved it
+    pub fn cached_token(&self, address: Address) -> Option<TokenMetadata> {
+        self.cache.tokens.get(&address).cloned()
+    }
+
+    /// fee tier of the pool, from the cache if its metadata is known
+    pub fn fee(&mut self, pool_address: Address) -> eyre::Result<u32> {
+        match self.cache.pools.get(&pool_address) {
+            Some(pool) => Ok(pool.fee),
+            None => self.inner.get_fee(pool_address),
+        }
+    }
+
+    /// current liquidity of the pool, this is not cached
+    pub fn liquidity(&mut self, pool_address: Address) -> eyre::Result<u128> {
+        self.inner.get_liquidity(pool_address)
+    }
+
+    /// adds a pool whose metadata is already known, e.g. from the factory events
+    pub fn insert_pool(&mut self, pool: PoolMetadata) {
+        self.cache.pools.insert(pool.pool_address, pool);
+    }
+
+    pub fn initial_pool(&mut self, pool_address: Address) -> eyre::Result<InitialPools> {
+        let pool = self.pool(pool_address)?;
+        self.resolve_tokens(pool_address, pool.token0, pool.token1, pool.creation_block)
+    }
+
+    /// a pool whose tokens and creation block are already known, with the token metadata
+    /// resolved
+    pub fn resolve_tokens(
+        &mut self,
+        pool_address: Address,
+        token0: Address,
+        token1: Address,
+        creation_block: u64,
+    ) -> eyre::Result<InitialPools> {
+        let token0 = self.token(token0)?;
+        let token1 = self.token(token1)?;
+
+        Ok(InitialPools {
+            pool_address,
+            token0_address: token0.address,
+            token0_decimals: token0.decimals,
+            token1_address: token1.address,
+            token1_decimals: token1.decimals,
+            creation_block,
+        })
+    }
+}
+
+impl PoolMetadataSource for PoolMetadataResolver {
+    fn token(&mut self, address: Address) -> eyre::Result<TokenMetadata> {
+        PoolMetadataResolver::token(self, address)
+    }
+
+    fn fee(&mut self, pool_address: Address) -> eyre::Result<u32> {
+        PoolMetadataResolver::fee(self, pool_address)
+    }
+
+    fn liquidity(&mut self, pool_address: Address) -> eyre::Result<u128> {
+        PoolMetadataResolver::liquidity(self, pool_address)
+    }
+}
+
+/// calls the token for its decimals, symbol and name, only failing without the decimals
+fn fetch_token(inner: &mut PoolDBInner, address: Address) -> eyre::Result<TokenMetadata> {
+    let decimals = inner.get_token_decimals(address)?;
+    let symbol = inner.get_token_symbol(address).unwrap_or_else(|e| {
+        warn!(target: "uniV3::metadata", "failed to get symbol for token {:?} - {:?}", address, e);
+        String::new()
+    });
+    let name = inner.get_token_name(address).unwrap_or_else(|e| {
+        warn!(target: "uniV3::metadata", "failed to get name for token {:?} - {:?}", address, e);
+        String::new()
+    });
+
+    Ok(TokenMetadata {
+        address,
+        decimals,
+        symbol,
+        name,
+    })
+}
+
+/// a `bytes32` symbol or name, as returned by tokens like MKR, without its trailing zeros
+pub(crate) fn bytes32_to_string(bytes: B256) -> String {
+    String::from_utf8_lossy(bytes.as_slice())
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_path;
+
+    fn discovered(pool: u8, creation_block: u64) -> DiscoveredPool {
+        DiscoveredPool {
+            pool_address: Address::with_last_byte(pool),
+            token0: Address::with_last_byte(100),
+            token1: Address::with_last_byte(101),
+            fee: 500,
+            tick_spacing: 10,
+            creation_block,
+        }
+    }
+
+    #[test]
+    fn test_discovery_resumes_after_scanned_blocks() {
+        let mut cache = MetadataCache::default();
+        assert_eq!(cache.discovery_start(), UNISWAP_V3_FACTORY_DEPLOYMENT_BLOCK);
+
+        cache.insert_discovered(
+            vec![discovered(2, 13_000_000), discovered(1, 12_500_000)],
+            14_000_000,
+        );
+        assert_eq!(cache.discovery_start(), 14_000_001);
+
+        // a pool found again isn't listed twice
+        cache.insert_discovered(
+            vec![discovered(3, 14_500_000), discovered(1, 12_500_000)],
+            15_000_000,
+        );
+        assert_eq!(cache.discovery_start(), 15_000_001);
+        assert_eq!(cache.discovery.pools.len(), 3);
+        assert_eq!(
+            cache.discovered_pools(15_000_000),
+            vec![
+                discovered(1, 12_500_000),
+                discovered(2, 13_000_000),
+                discovered(3, 14_500_000)
+            ]
+        );
+
+        // an earlier end block only returns the pools created up to it
+        assert_eq!(
+            cache.discovered_pools(13_000_000),
+            vec![discovered(1, 12_500_000), discovered(2, 13_000_000)]
+        );
+
+        let cache: MetadataCache =
+            serde_json::from_str(&serde_json::to_string(&cache).unwrap()).unwrap();
+        assert_eq!(cache.discovery_start(), 15_000_001);
+        assert_eq!(cache.discovered_pools(15_000_000).len(), 3);
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let path = temp_path("metadata.json");
+        assert!(MetadataCache::load(&path).unwrap().tokens.is_empty());
+
+        let mut cache = MetadataCache::default();
+        let token = TokenMetadata {
+            address: Address::with_last_byte(100),
+            decimals: 18,
+            symbol: "MKR".to_string(),
+            name: "Maker".to_string(),
+        };
+        cache.tokens.insert(token.address, token.clone());
+        cache.insert_discovered(vec![discovered(1, 12_500_000)], 13_000_000);
+        cache.save(&path).unwrap();
+
+        let loaded = MetadataCache::load(&path).unwrap();
+        assert_eq!(loaded.tokens.get(&token.address), Some(&token));
+        assert_eq!(loaded.pools, cache.pools);
+        assert_eq!(loaded.discovery, cache.discovery);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_bytes32_to_string() {
+        let mut bytes = B256::ZERO;
+        bytes[..3].copy_from_slice(b"MKR");
+        assert_eq!(bytes32_to_string(bytes), "MKR");
+        assert_eq!(bytes32_to_string(B256::ZERO), "");
+    }
+}
@@ -0,0 +1,105 @@
+use std::{net::SocketAddr, time::Duration};
+
+use metrics::{
+    counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit,
+};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use tracing::info;
+
+use crate::{pools::FetcherKind, retry::FailedStage};
+
+// the metrics of each component are prefixed like the tracing target it logs to
+const BLOCKS_PROCESSED: &str = "uniV3_blocks_processed_total";
+const BLOCKS_SKIPPED: &str = "uniV3_blocks_skipped_total";
+const RETRIES: &str = "uniV3_retries_total";
+const DEAD_LETTERS: &str = "uniV3_dead_letters_total";
+const EVM_CALLS: &str = "uniV3_fetcher_evm_calls_total";
+const TRACE_FETCH_SECONDS: &str = "uniV3_fetcher_trace_fetch_seconds";
+const QUEUED_ROWS: &str = "uniV3_db_queued_rows";
+const QUEUED_BLOCKS: &str = "uniV3_db_queued_blocks";
+const INSERT_SECONDS: &str = "uniV3_db_insert_seconds";
+const INSERT_FAILURES: &str = "uniV3_db_insert_failures_total";
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// serves the metrics in the prometheus text format on `addr`, the metrics are dropped when
+/// the exporter isn't installed
+pub fn install_exporter(addr: SocketAddr) -> eyre::Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .set_buckets(LATENCY_BUCKETS)?
+        .install()?;
+
+    describe_counter!(
+        BLOCKS_PROCESSED,
+        "blocks whose values were sent to the db writer"
+    );
+    describe_counter!(BLOCKS_SKIPPED, "blocks with no logs from the tracked pools");
+    describe_counter!(RETRIES, "retried blocks, inserts and retractions");
+    describe_counter!(
+        DEAD_LETTERS,
+        "blocks, inserts and retractions that were given up on"
+    );
+    describe_counter!(
+        EVM_CALLS,
+        "calls to the pool contracts made by each fetcher"
+    );
+    describe_histogram!(TRACE_FETCH_SECONDS, Unit::Seconds, "time to trace a block");
+    describe_gauge!(
+        QUEUED_ROWS,
+        "values buffered by the db writer that have not been inserted"
+    );
+    describe_gauge!(
+        QUEUED_BLOCKS,
+        "blocks buffered by the db writer that have not been inserted"
+    );
+    describe_histogram!(
+        INSERT_SECONDS,
+        Unit::Seconds,
+        "time to insert a batch of values into each sink"
+    );
+    describe_counter!(
+        INSERT_FAILURES,
+        "failed inserts, including the ones that were retried"
+    );
+
+    info!(target: "uniV3", "serving metrics on http://{addr}/metrics");
+
+    Ok(())
+}
+
+pub fn record_blocks_processed(blocks: u64, skipped: u64) {
+    counter!(BLOCKS_PROCESSED, blocks);
+    counter!(BLOCKS_SKIPPED, skipped);
+}
+
+pub fn record_retry(stage: FailedStage) {
+    counter!(RETRIES, 1, "stage" => stage.as_str());
+}
+
+pub fn record_dead_letter(stage: FailedStage) {
+    counter!(DEAD_LETTERS, 1, "stage" => stage.as_str());
+}
+
+pub fn record_evm_calls(kind: FetcherKind, calls: u64) {
+    counter!(EVM_CALLS, calls, "fetcher" => kind.as_str());
+}
+
+pub fn record_trace_fetch(elapsed: Duration) {
+    histogram!(TRACE_FETCH_SECONDS, elapsed.as_secs_f64());
+}
+
+pub fn record_queue_depth(rows: usize, blocks: usize) {
+    gauge!(QUEUED_ROWS, rows as f64);
+    gauge!(QUEUED_BLOCKS, blocks as f64);
+}
+
+pub fn record_insert(sink: &'static str, elapsed: Duration) {
+    histogram!(INSERT_SECONDS, elapsed.as_secs_f64(), "sink" => sink);
+}
+
+pub fn record_insert_failure(sink: &'static str) {
+    counter!(INSERT_FAILURES, 1, "sink" => sink);
+}
@@ -1,5 +1,7 @@
 use alloy_primitives::Address;
+use alloy_primitives::BloomInput;
 use alloy_primitives::TxHash;
+use alloy_primitives::B256;
 use alloy_rpc_types::BlockId;
 use alloy_rpc_types_trace::parity::Action;
 use alloy_rpc_types_trace::parity::TraceOutput;
@@ -8,7 +10,12 @@ use alloy_rpc_types_trace::parity::TraceType;
 
 use reth_api_libmdbx::RethDbApiClient;
 use reth_primitives::Bytes;
+use reth_primitives::Header;
+use reth_primitives::Receipt;
 use reth_primitives::SealedBlockWithSenders;
+use reth_provider::BlockHashReader;
+use reth_provider::HeaderProvider;
+use reth_provider::ReceiptProvider;
 use reth_provider::StateProvider;
 use reth_revm::{
     database::StateProviderDatabase,
@@ -59,6 +66,95 @@ impl EthNodeApi {
         Ok(StateProviderDatabase::new(state_provider))
     }
 
+    pub fn get_header(&self, block_number: u64) -> eyre::Result<Header> {
+        self.reth_api
+            .eth_api
+            .provider()
+            .header_by_number(block_number)?
+            .ok_or(eyre::ErrReport::msg(format!(
+                "no header found for block {block_number}"
+            )))
+    }
+
+    /// the canonical hash at `block_number`, none if the chain is shorter
+    pub fn get_block_hash(&self, block_number: u64) -> eyre::Result<Option<B256>> {
+        Ok(self.reth_api.eth_api.provider().block_hash(block_number)?)
+    }
+
+    pub fn get_receipts(&self, block_number: u64) -> eyre::Result<Vec<Receipt>> {
+        self.reth_api
+            .eth_api
+            .provider()
+            .receipts_by_block(block_number.into())?
+            .ok_or(eyre::ErrReport::msg(format!(
+                "no receipts found for block {block_number}"
+            )))
+    }
+
+    /// binary searches for the first block with code deployed at `address`, if any
+    pub fn get_contract_creation_block(
+        &self,
+        address: Address,
+        start_block: u64,
+        end_block: u64,
+    ) -> eyre::Result<Option<u64>> {
+        let has_code = |block_number: u64| -> eyre::Result<bool> {
+            let state = self
+                .reth_api
+                .eth_api
+                .state_at_block_id(block_number.into())?;
+            Ok(state
+                .account_code(address)?
+                .is_some_and(|code| !code.is_empty()))
+        };
+
+        if !has_code(end_block)? {
+            return Ok(None);
+        }
+
+        let (mut low, mut high) = (start_block, end_block);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if has_code(mid)? {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        Ok(Some(low))
+    }
+
+    /// the subset of `addresses` that emitted a log in the block, the header's logs bloom
+    /// is checked first so the receipts are only read when it matches
+    pub fn filter_addresses_with_logs(
+        &self,
+        header: &Header,
+        addresses: &HashSet<Address>,
+    ) -> eyre::Result<HashSet<Address>> {
+        let in_bloom = addresses
+            .iter()
+            .filter(|address| {
+                header
+                    .logs_bloom
+                    .contains_input(BloomInput::Raw(address.as_slice()))
+            })
+            .copied()
+            .collect::<HashSet<_>>();
+
+        if in_bloom.is_empty() {
+            return Ok(in_bloom);
+        }
+
+        Ok(self
+            .get_receipts(header.number)?
+            .into_iter()
+            .flat_map(|receipt| receipt.logs)
+            .map(|log| log.address)
+            .filter(|address| in_bloom.contains(address))
+            .collect())
+    }
+
     pub async fn get_block_with_signers(
         &self,
         block_number: u64,
@@ -91,14 +187,14 @@ impl EthNodeApi {
 }
 
 pub fn filter_traces_by_address_set_to_tx_hash(
-    tx: TraceResultsWithTransactionHash,
+    tx: &TraceResultsWithTransactionHash,
     addresses: &[Address],
 ) -> Vec<(Address, TxHash)> {
     let address_set = addresses.iter().map(|a| *a).collect::<HashSet<_>>();
     tx.full_trace
         .trace
-        .into_iter()
-        .filter_map(|trace| match trace.action {
+        .iter()
+        .filter_map(|trace| match &trace.action {
             Action::Call(call) => {
                 if let Some(f) = address_set.get(&call.from) {
                     Some((*f, tx.transaction_hash))
@@ -114,7 +210,7 @@ pub fn filter_traces_by_address_set_to_tx_hash(
 }
 
 pub fn filter_traces_by_address_to_call_input(
-    tx: TraceResultsWithTransactionHash,
+    tx: &TraceResultsWithTransactionHash,
     addresses: &[Address],
 ) -> Vec<(Address, FilteredTraceCall)> {
     let address_set = addresses.iter().map(|a| *a).collect::<HashSet<_>>();
@@ -122,24 +218,24 @@ pub fn filter_traces_by_address_to_call_input(
     let traces = tx
         .full_trace
         .trace
-        .into_iter()
+        .iter()
         .filter_map(|trace| {
             if trace.error.is_some() {
                 failed = true;
                 return None;
             }
 
-            match trace.action {
+            match &trace.action {
                 Action::Call(call) => {
                     if let Some(f) = address_set.get(&call.to) {
-                        if let Some(ret) = trace.result {
+                        if let Some(ret) = &trace.result {
                             match ret {
                                 TraceOutput::Call(call_ret) => Some((
                                     *f,
                                     FilteredTraceCall::new(
                                         tx.transaction_hash,
-                                        call.input,
-                                        call_ret.output,
+                                        call.input.clone(),
+                                        call_ret.output.clone(),
                                     ),
                                 )),
 
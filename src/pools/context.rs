@@ -0,0 +1,53 @@
+use alloy_rpc_types_trace::parity::TraceResultsWithTransactionHash;
+use reth_primitives::{SealedHeader, TransactionSignedEcRecovered, Withdrawal};
+
+use std::time::Instant;
+
+use crate::{metrics, node::EthNodeApi};
+
+/// the block, its senders and (when needed) its traces, fetched once and shared between the
+/// decoded and re-executed fetchers
+pub struct BlockContext {
+    pub block_number: u64,
+    pub header: SealedHeader,
+    pub transactions: Vec<TransactionSignedEcRecovered>,
+    pub traces: Vec<TraceResultsWithTransactionHash>,
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+impl BlockContext {
+    /// `with_traces` is false when none of the block's fetchers read the traces, which skips
+    /// replaying the block with the tracer
+    pub async fn new(
+        node: &EthNodeApi,
+        block_number: u64,
+        with_traces: bool,
+    ) -> eyre::Result<Self> {
+        let (block, traces) = tokio::try_join!(node.get_block_with_signers(block_number), async {
+            if with_traces {
+                let started = Instant::now();
+                let traces = node.get_transaction_traces(block_number).await;
+                metrics::record_trace_fetch(started.elapsed());
+                traces
+            } else {
+                Ok(Vec::new())
+            }
+        })?;
+
+        Ok(Self {
+            block_number,
+            header: block.header.clone(),
+            withdrawals: block
+                .withdrawals
+                .iter()
+                .flat_map(|withdrawals| withdrawals.iter().cloned())
+                .collect(),
+            transactions: block.into_transactions_ecrecovered().collect(),
+            traces,
+        })
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.header.timestamp
+    }
+}
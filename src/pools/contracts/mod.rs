@@ -4,3 +4,28 @@ sol! {
    #[derive(Debug)]
    UniswapV3, "src/pools/contracts/abis/univ3.json"
 }
+
+sol! {
+   #[derive(Debug)]
+   interface UniswapV3Factory {
+      event PoolCreated(address indexed token0, address indexed token1, uint24 indexed fee, int24 tickSpacing, address pool);
+   }
+}
+
+sol! {
+   #[derive(Debug)]
+   interface ERC20 {
+      function decimals() external view returns (uint8);
+      function symbol() external view returns (string);
+      function name() external view returns (string);
+   }
+}
+
+sol! {
+   /// some early tokens (e.g. MKR) return their symbol and name as `bytes32`
+   #[derive(Debug)]
+   interface ERC20Bytes32 {
+      function symbol() external view returns (bytes32);
+      function name() external view returns (bytes32);
+   }
+}
@@ -1,5 +1,8 @@
 use crate::{
+    backlog::PoolDataSender,
     execute_on_threadpool,
+    metadata::bytes32_to_string,
+    metrics,
     node::{
         filter_traces_by_address_set_to_tx_hash, filter_traces_by_address_to_call_input, EthNodeApi,
     },
@@ -7,41 +10,107 @@ use crate::{
 use alloy_primitives::Address;
 use alloy_rpc_types_trace::parity::TraceResultsWithTransactionHash;
 use alloy_sol_types::SolCall;
+use clap::ValueEnum;
 use itertools::Itertools;
 use reth_primitives::revm::env::tx_env_with_recovered;
 
-use super::{PoolFetcher, UniswapV3};
-use crate::pools::types::PoolData;
+use super::{BlockContext, ERC20Bytes32, PoolFetcher, UniswapV3, ERC20};
+use crate::pools::types::{PoolBlockData, PoolData};
 
-use alloy_primitives::{TxHash, U256};
+use alloy_primitives::{address, TxHash, B256, U256};
 
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use reth_primitives::TransactionSignedEcRecovered;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use reth_primitives::{constants::GWEI_TO_WEI, Header, TransactionSignedEcRecovered, Withdrawal};
 use reth_provider::StateProvider;
 use reth_revm::{
     database::StateProviderDatabase,
     db::CacheDB,
-    primitives::{BlockEnv, CfgEnvWithHandlerCfg, EnvWithHandlerCfg, TransactTo, TxEnv},
-    DatabaseCommit,
+    primitives::{
+        BlockEnv, CfgEnvWithHandlerCfg, EnvWithHandlerCfg, ResultAndState, State as EvmState,
+        TransactTo, TxEnv,
+    },
+    Database, DatabaseCommit,
 };
 use reth_rpc::eth::EthTransactions;
-use std::{collections::HashSet, ops::Range, sync::Arc};
-use tokio::sync::mpsc::UnboundedSender;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::Arc,
+};
 use tracing::{debug, info};
 
+pub struct CompletedBlock {
+    pub block_number: u64,
+    /// the hash of the block the values were produced from
+    pub block_hash: B256,
+    /// the number of pool fetchers the block was run for
+    pub tasks: usize,
+    /// none of the pools emitted a log in the block, so it was never traced
+    pub skipped: bool,
+}
+
+impl CompletedBlock {
+    pub fn new(block_number: u64, block_hash: B256, tasks: usize, skipped: bool) -> Self {
+        Self {
+            block_number,
+            block_hash,
+            tasks,
+            skipped,
+        }
+    }
+}
+
+/// how the re-executed fetchers find the transactions that changed a pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ChangeDetection {
+    /// transactions whose call traces touch the pool
+    #[default]
+    Traces,
+    /// transactions that changed the pool's storage when replayed, the block is never traced
+    StateDiff,
+}
+
+/// the transactions of a block that changed a pool
+pub enum PoolTxs {
+    Traced(HashSet<TxHash>),
+    /// unknown until each transaction is replayed, up to the index of the last transaction
+    /// with a log from the pool, as the pool's storage never changes without one
+    StateDiff(Option<usize>),
+}
+
+impl PoolTxs {
+    fn is_empty(&self) -> bool {
+        match self {
+            PoolTxs::Traced(hashes) => hashes.is_empty(),
+            PoolTxs::StateDiff(last_pool_tx) => last_pool_tx.is_none(),
+        }
+    }
+
+    fn changed_pool(&self, pool_address: Address, tx_hash: TxHash, state: &EvmState) -> bool {
+        match self {
+            PoolTxs::Traced(hashes) => hashes.contains(&tx_hash),
+            PoolTxs::StateDiff(_) => state
+                .get(&pool_address)
+                .is_some_and(|account| account.storage.values().any(|slot| slot.is_changed())),
+        }
+    }
+}
+
 pub struct PoolCaller {
     pub node: Arc<EthNodeApi>,
-    pub db_tx: UnboundedSender<Vec<PoolData>>,
+    pub db_tx: PoolDataSender,
     pub pools: Vec<Arc<Box<dyn PoolFetcher>>>,
     pub block_number: u64,
+    pub change_detection: ChangeDetection,
 }
 
 impl PoolCaller {
     pub fn new(
         node: Arc<EthNodeApi>,
-        db_tx: UnboundedSender<Vec<PoolData>>,
+        db_tx: PoolDataSender,
         pools: &[Arc<Box<dyn PoolFetcher>>],
         block_number: u64,
+        change_detection: ChangeDetection,
     ) -> Self {
         let pools = pools
             .iter()
@@ -53,27 +122,82 @@ impl PoolCaller {
             db_tx,
             pools,
             block_number,
+            change_detection,
         }
     }
 
-    pub async fn execute_block(self) -> Result<usize, (u64, eyre::ErrReport)> {
-        let data = self.run_block().await.map_err(|e| (self.block_number, e))?;
+    pub async fn execute_block(mut self) -> Result<CompletedBlock, (u64, eyre::ErrReport)> {
+        let block_number = self.block_number;
+        let tasks = self.pools.len();
 
+        let header = self.retain_touched_pools().map_err(|e| (block_number, e))?;
+
+        if self.pools.is_empty() {
+            self.send_skipped_block(&header, tasks)
+                .map_err(|e| (block_number, e))?;
+
+            return Ok(CompletedBlock::new(
+                block_number,
+                header.hash_slow(),
+                tasks,
+                true,
+            ));
+        }
+
+        let ctx = self.block_context().await.map_err(|e| (block_number, e))?;
+        let data = self.run_block(&ctx).await.map_err(|e| (block_number, e))?;
+
+        self.db_tx
+            .send(PoolBlockData::new(block_number, ctx.timestamp(), data).into())
+            .map_err(|e| (block_number, e.into()))?;
+
+        Ok(CompletedBlock::new(
+            block_number,
+            ctx.header.hash(),
+            tasks,
+            false,
+        ))
+    }
+
+    /// drops the pools that did not emit a log in the block
+    pub fn retain_touched_pools(&mut self) -> eyre::Result<Header> {
+        let header = self.node.get_header(self.block_number)?;
+        let addresses = self
+            .pools
+            .iter()
+            .map(|pool| pool.pool_address())
+            .collect::<HashSet<_>>();
+        let touched = self.node.filter_addresses_with_logs(&header, &addresses)?;
+        self.pools
+            .retain(|pool| touched.contains(&pool.pool_address()));
+
+        Ok(header)
+    }
+
+    pub fn send_skipped_block(&self, header: &Header, tasks: usize) -> eyre::Result<()> {
+        debug!(target: "uniV3::fetcher", "skipping block {} with no logs from {} pools", self.block_number, tasks);
         self.db_tx
-            .send(data)
-            .map_err(|e| (self.block_number, e.into()))?;
+            .send(PoolBlockData::new(self.block_number, header.timestamp, Vec::new()).into())?;
 
-        Ok(self.pools.len())
+        Ok(())
     }
 
-    async fn run_block(&self) -> eyre::Result<Vec<PoolData>> {
+    pub async fn block_context(&self) -> eyre::Result<BlockContext> {
+        // re-executed fetchers only need the traces when detecting changes from them
+        let with_traces = self.pools.iter().any(|pool| pool.is_decoded())
+            || self.change_detection == ChangeDetection::Traces;
+
+        BlockContext::new(&self.node, self.block_number, with_traces).await
+    }
+
+    async fn run_block(&self, ctx: &BlockContext) -> eyre::Result<Vec<PoolData>> {
         let (re_executed, decoded) =
-            tokio::try_join!(self.re_execute_block(), self.decode_block())?;
+            tokio::try_join!(self.re_execute_block(ctx), self.decode_block(ctx))?;
 
         Ok(re_executed.into_iter().chain(decoded).collect())
     }
 
-    async fn decode_block(&self) -> eyre::Result<Vec<PoolData>> {
+    pub async fn decode_block(&self, ctx: &BlockContext) -> eyre::Result<Vec<PoolData>> {
         let addresses = self
             .pools
             .iter()
@@ -81,35 +205,20 @@ impl PoolCaller {
             .map(|pool| pool.pool_address())
             .collect::<Vec<_>>();
 
-        let block_traces = self.node.get_transaction_traces(self.block_number).await?;
-
-        if block_traces.is_empty() {
+        if addresses.is_empty() || ctx.traces.is_empty() {
             debug!(target: "uniV3::fetcher", "no transactions found in block {} for {} pools", self.block_number,self.pools.len());
             return Ok(Vec::new());
         }
 
         let state = execute_on_threadpool(|| {
-            self.decode_transactions(self.block_number, addresses, block_traces)
+            self.decode_transactions(self.block_number, addresses, &ctx.traces)
         })?;
         info!(target: "uniV3::fetcher", "completed block {} for {} pools with {} total values", self.block_number, self.pools.len(), state.len());
 
         Ok(state)
     }
 
-    async fn re_execute_block(&self) -> eyre::Result<Vec<PoolData>> {
-        let pool_inner = PoolDBInner::new(self.node.clone(), self.block_number).await?;
-        let parent_block_txs = self
-            .node
-            .get_block_with_signers(self.block_number)
-            .await?
-            .into_transactions_ecrecovered()
-            .collect::<Vec<_>>();
-
-        if parent_block_txs.is_empty() {
-            debug!(target: "uniV3::fetcher", "no transactions found in block {} for {} pools", self.block_number,self.pools.len());
-            return Ok(Vec::new());
-        }
-
+    async fn re_execute_block(&self, ctx: &BlockContext) -> eyre::Result<Vec<PoolData>> {
         let addresses = self
             .pools
             .iter()
@@ -117,10 +226,16 @@ impl PoolCaller {
             .map(|pool| pool.pool_address())
             .collect::<Vec<_>>();
 
-        let block_traces = self.node.get_transaction_traces(self.block_number).await?;
+        if addresses.is_empty() || ctx.transactions.is_empty() {
+            debug!(target: "uniV3::fetcher", "no transactions found in block {} for {} pools", self.block_number,self.pools.len());
+            return Ok(Vec::new());
+        }
+
+        let mut pool_inner = PoolDBInner::new(self.node.clone(), self.block_number).await?;
+        pool_inner.apply_beacon_root(&ctx.header)?;
 
         let state = execute_on_threadpool(|| {
-            self.re_execute_transactions(pool_inner, &parent_block_txs, addresses, block_traces)
+            self.re_execute_transactions(pool_inner, &ctx.transactions, addresses, &ctx.traces)
         })?;
         info!(target: "uniV3::fetcher", "completed block {} for {} pools with {} total values", self.block_number, self.pools.len(), state.len());
 
@@ -131,10 +246,10 @@ impl PoolCaller {
         &self,
         block_number: u64,
         addresses: Vec<Address>,
-        block_traces: Vec<TraceResultsWithTransactionHash>,
+        block_traces: &[TraceResultsWithTransactionHash],
     ) -> eyre::Result<Vec<PoolData>> {
         let pool_traces = block_traces
-            .into_iter()
+            .iter()
             .flat_map(|trace| filter_traces_by_address_to_call_input(trace, &addresses))
             .into_group_map();
 
@@ -157,41 +272,82 @@ impl PoolCaller {
         Ok(state)
     }
 
-    fn re_execute_transactions(
+    /// the re-executed pools and the transactions in the block that changed each
+    pub fn re_executed_pool_txs(
         &self,
-        inner: PoolDBInner,
-        parent_block_txs: &[TransactionSignedEcRecovered],
         addresses: Vec<Address>,
-        block_traces: Vec<TraceResultsWithTransactionHash>,
-    ) -> eyre::Result<Vec<PoolData>> {
-        let pool_txs = block_traces
-            .into_iter()
-            .flat_map(|trace| filter_traces_by_address_set_to_tx_hash(trace, &addresses))
-            .collect::<Vec<_>>();
+        block_traces: &[TraceResultsWithTransactionHash],
+    ) -> eyre::Result<Vec<(Arc<Box<dyn PoolFetcher>>, PoolTxs)>> {
+        let traced_txs = match self.change_detection {
+            ChangeDetection::Traces => block_traces
+                .iter()
+                .flat_map(|trace| filter_traces_by_address_set_to_tx_hash(trace, &addresses))
+                .collect::<Vec<_>>(),
+            ChangeDetection::StateDiff => Vec::new(),
+        };
 
-        let state = self
+        // the receipts are in transaction order, so the last index kept for each address is
+        // its last transaction with a log
+        let last_log_txs = match self.change_detection {
+            ChangeDetection::Traces => HashMap::new(),
+            ChangeDetection::StateDiff => self
+                .node
+                .get_receipts(self.block_number)?
+                .into_iter()
+                .enumerate()
+                .flat_map(|(tx_index, receipt)| {
+                    receipt
+                        .logs
+                        .into_iter()
+                        .map(move |log| (log.address, tx_index))
+                })
+                .collect::<HashMap<_, _>>(),
+        };
+
+        Ok(self
             .pools
-            .par_iter()
+            .iter()
             .filter(|pool| pool.is_re_executed())
             .map(|pool| {
-                let pool_txs = pool_txs
-                    .iter()
-                    .filter(|(p, _)| p == &pool.pool_address())
-                    .map(|(_, t)| *t)
-                    .collect::<HashSet<_>>();
+                let pool_txs = match self.change_detection {
+                    ChangeDetection::Traces => PoolTxs::Traced(
+                        traced_txs
+                            .iter()
+                            .filter(|(p, _)| p == &pool.pool_address())
+                            .map(|(_, t)| *t)
+                            .collect::<HashSet<_>>(),
+                    ),
+                    ChangeDetection::StateDiff => {
+                        PoolTxs::StateDiff(last_log_txs.get(&pool.pool_address()).copied())
+                    }
+                };
 
+                (pool.clone(), pool_txs)
+            })
+            .collect())
+    }
+
+    fn re_execute_transactions(
+        &self,
+        inner: PoolDBInner,
+        parent_block_txs: &[TransactionSignedEcRecovered],
+        addresses: Vec<Address>,
+        block_traces: &[TraceResultsWithTransactionHash],
+    ) -> eyre::Result<Vec<PoolData>> {
+        let state = self
+            .re_executed_pool_txs(addresses, block_traces)?
+            .into_par_iter()
+            .map(|(pool, pool_txs)| {
                 if pool_txs.is_empty() {
                     Ok(Vec::new())
                 } else {
-                    let inner = inner.clone();
+                    let mut inner = inner.clone();
                     inner.execute_cycle(
                         self.block_number,
                         parent_block_txs,
                         pool.pool_address(),
                         pool_txs,
-                        |db_inner, bn, tx, tx_index| {
-                            pool.re_execute_block(db_inner, bn, tx, tx_index)
-                        },
+                        |db_inner, bn, tx, tx_index| db_inner.run_fetcher(&pool, bn, tx, tx_index),
                     )
                 }
             })
@@ -211,25 +367,26 @@ pub struct PoolDBInner {
     pub cfg: CfgEnvWithHandlerCfg,
     pub env: EnvWithHandlerCfg,
     pub block_env: BlockEnv,
+    /// calls made to contracts, for the metrics
+    pub evm_calls: u64,
 }
 
 impl PoolDBInner {
+    /// the state at the start of `block_number`, after its parent block
     pub async fn new(node: Arc<EthNodeApi>, block_number: u64) -> eyre::Result<Self> {
-        let parent_block = block_number - 1;
+        let parent_block = block_number.checked_sub(1).ok_or(eyre::ErrReport::msg(
+            "the genesis block has no parent state to replay it on",
+        ))?;
         let state_db = node.state_provider_db(parent_block)?;
-        let (cfg_env, mut block_env, _) = node.get_evm_env_at(block_number).await?;
-        block_env.basefee = U256::ZERO;
+        let (cfg_env, block_env, _) = node.get_evm_env_at(block_number).await?;
 
         Ok(Self {
             node,
             state_db: CacheDB::new(Arc::new(state_db)),
             cfg: cfg_env.clone(),
-            env: EnvWithHandlerCfg::new_with_cfg_env(
-                cfg_env,
-                block_env.clone(),
-                Default::default(),
-            ),
+            env: call_env(cfg_env, &block_env),
             block_env,
+            evm_calls: 0,
         })
     }
 
@@ -277,7 +434,79 @@ impl PoolDBInner {
         Ok(self.transact_call(call, to)?)
     }
 
+    pub fn get_pool_tokens(&mut self, to: Address) -> eyre::Result<(Address, Address)> {
+        let token0 = self.transact_call(UniswapV3::token0Call {}, to)?._0;
+        let token1 = self.transact_call(UniswapV3::token1Call {}, to)?._0;
+
+        Ok((token0, token1))
+    }
+
+    pub fn get_fee_growth_globals(&mut self, to: Address) -> eyre::Result<(U256, U256)> {
+        let fee_growth_0 = self
+            .transact_call(UniswapV3::feeGrowthGlobal0X128Call {}, to)?
+            ._0;
+        let fee_growth_1 = self
+            .transact_call(UniswapV3::feeGrowthGlobal1X128Call {}, to)?
+            ._0;
+
+        Ok((fee_growth_0, fee_growth_1))
+    }
+
+    pub fn get_protocol_fees(&mut self, to: Address) -> eyre::Result<(u128, u128)> {
+        let fees = self.transact_call(UniswapV3::protocolFeesCall {}, to)?;
+        Ok((fees.token0, fees.token1))
+    }
+
+    pub fn get_liquidity(&mut self, to: Address) -> eyre::Result<u128> {
+        let request = UniswapV3::liquidityCall {};
+        Ok(self.transact_call(request, to)?._0)
+    }
+
+    pub fn get_fee(&mut self, to: Address) -> eyre::Result<u32> {
+        let request = UniswapV3::feeCall {};
+        Ok(self.transact_call(request, to)?._0)
+    }
+
+    pub fn get_token_decimals(&mut self, to: Address) -> eyre::Result<u8> {
+        let request = ERC20::decimalsCall {};
+        Ok(self.transact_call(request, to)?._0)
+    }
+
+    pub fn get_token_symbol(&mut self, to: Address) -> eyre::Result<String> {
+        match self.transact_call(ERC20::symbolCall {}, to) {
+            Ok(symbol) => Ok(symbol._0),
+            Err(_) => Ok(bytes32_to_string(
+                self.transact_call(ERC20Bytes32::symbolCall {}, to)?._0,
+            )),
+        }
+    }
+
+    pub fn get_token_name(&mut self, to: Address) -> eyre::Result<String> {
+        match self.transact_call(ERC20::nameCall {}, to) {
+            Ok(name) => Ok(name._0),
+            Err(_) => Ok(bytes32_to_string(
+                self.transact_call(ERC20Bytes32::nameCall {}, to)?._0,
+            )),
+        }
+    }
+
+    /// runs the fetcher on the state after the transaction, counting the calls it makes
+    pub fn run_fetcher(
+        &mut self,
+        pool: &Arc<Box<dyn PoolFetcher>>,
+        block_number: u64,
+        tx_hash: TxHash,
+        tx_index: u64,
+    ) -> eyre::Result<Vec<PoolData>> {
+        let evm_calls = self.evm_calls;
+        let res = pool.re_execute_block(self, block_number, tx_hash, tx_index);
+        metrics::record_evm_calls(pool.kind(), self.evm_calls - evm_calls);
+
+        res
+    }
+
     fn transact_call<C: SolCall>(&mut self, call: C, to: Address) -> eyre::Result<C::Return> {
+        self.evm_calls += 1;
         let mut env = self.env.clone();
         env.tx = TxEnv {
             transact_to: TransactTo::Call(to),
@@ -311,59 +540,244 @@ impl PoolDBInner {
         }
     }
 
-    fn execute_cycle<F>(
-        mut self,
+    /// replays the transactions up to the pool's last one, calling `f` after each that changed
+    /// it, and leaves the state after that transaction
+    pub fn execute_cycle<F>(
+        &mut self,
         block_number: u64,
         parent_block_txs: &[TransactionSignedEcRecovered],
         pool_address: Address,
-        pool_txs: HashSet<TxHash>,
+        pool_txs: PoolTxs,
         f: F,
     ) -> eyre::Result<Vec<PoolData>>
     where
         F: Fn(&mut PoolDBInner, u64, TxHash, u64) -> eyre::Result<Vec<PoolData>>,
     {
-        let pool_states = parent_block_txs
+        // nothing after the pool's last transaction can change the values we read
+        let last_pool_tx = match &pool_txs {
+            PoolTxs::Traced(hashes) => {
+                let Some(last_pool_tx) = parent_block_txs
+                    .iter()
+                    .rposition(|transaction| hashes.contains(&transaction.hash))
+                else {
+                    return Ok(Vec::new());
+                };
+                last_pool_tx
+            }
+            PoolTxs::StateDiff(last_pool_tx) => {
+                let Some(last_pool_tx) = *last_pool_tx else {
+                    return Ok(Vec::new());
+                };
+                last_pool_tx
+            }
+        };
+        debug!(target: "uniV3::fetcher", "replaying {} of {} transactions in block {} for pool {}", last_pool_tx + 1, parent_block_txs.len(), block_number, pool_address);
+
+        let pool_states = parent_block_txs[..=last_pool_tx]
             .iter()
             .enumerate()
             .map(|(tx_index, transaction)| {
-                let tx = tx_env_with_recovered(transaction);
-
-                let env = EnvWithHandlerCfg::new_with_cfg_env(
-                    self.cfg.clone(),
-                    self.block_env.clone(),
-                    tx,
-                );
-
-                if let Ok((res, _)) = self
-                    .node
-                    .reth_api
-                    .eth_api
-                    .transact(&mut self.state_db, env)
-                    .map_err(|e| {
-                        eyre::ErrReport::msg(format!("{:?} - {:?}", transaction.hash, e))
-                    }) {
+                match self.replay_transaction(transaction) {
+                    Ok(res) => {
+                        let changed_pool =
+                            pool_txs.changed_pool(pool_address, transaction.hash, &res.state);
                         self.state_db.commit(res.state);
 
                         if res.result.is_success() {
-                            if let Some(pool_tx) = pool_txs.get(&transaction.hash) {
-                                return Ok(Some(f(&mut self, block_number, *pool_tx, tx_index as u64)?));
+                            if changed_pool {
+                                return Ok(Some(f(
+                                    self,
+                                    block_number,
+                                    transaction.hash,
+                                    tx_index as u64,
+                                )?));
                             }
                         } else {
                             debug!(target: "uniV3::fetcher", "tx reverted in sim: {:?}", transaction.hash);
                         }
                     }
-
-
+                    Err(e) => debug!(target: "uniV3::fetcher", "{:?}", e),
+                }
 
                 Ok(None)
             })
             .collect::<eyre::Result<Vec<_>>>()?
             .into_iter()
-            .flatten().flatten()
+            .flatten()
+            .flatten()
             .collect::<Vec<_>>();
 
         debug!(target: "uniV3::fetcher", "completed block {} for pool {} with {} total ticks", block_number,pool_address, pool_states.len());
 
         Ok(pool_states)
     }
+
+    /// replays every transaction of the block on top of the current state, calling each
+    /// pool's fetcher after the transactions that changed it
+    ///
+    /// the state is left at the end of the block so the next block can be replayed on top of it
+    pub fn replay_block(
+        &mut self,
+        ctx: &BlockContext,
+        pools: &[(Arc<Box<dyn PoolFetcher>>, PoolTxs)],
+    ) -> eyre::Result<Vec<PoolData>> {
+        let mut pool_states = Vec::new();
+
+        self.apply_beacon_root(&ctx.header)?;
+        for (tx_index, transaction) in ctx.transactions.iter().enumerate() {
+            let res = self.replay_transaction(transaction)?;
+
+            let changed_pools = pools
+                .iter()
+                .filter(|(pool, pool_txs)| {
+                    pool_txs.changed_pool(pool.pool_address(), transaction.hash, &res.state)
+                })
+                .collect::<Vec<_>>();
+            self.state_db.commit(res.state);
+
+            if !res.result.is_success() {
+                debug!(target: "uniV3::fetcher", "tx reverted in sim: {:?}", transaction.hash);
+                continue;
+            }
+
+            for (pool, _) in changed_pools {
+                pool_states.extend(self.run_fetcher(
+                    pool,
+                    ctx.block_number,
+                    transaction.hash,
+                    tx_index as u64,
+                )?);
+            }
+        }
+
+        self.apply_withdrawals(&ctx.withdrawals)?;
+        debug!(target: "uniV3::fetcher", "replayed {} transactions in block {} for {} pools with {} total values", ctx.transactions.len(), ctx.block_number, pools.len(), pool_states.len());
+
+        Ok(pool_states)
+    }
+
+    /// replays the transactions on top of the current state, failing on the first one that
+    /// can't be replayed rather than reading a state that never existed
+    pub fn replay_transactions(
+        &mut self,
+        transactions: &[TransactionSignedEcRecovered],
+    ) -> eyre::Result<()> {
+        for transaction in transactions {
+            let res = self.replay_transaction(transaction)?;
+            if !res.result.is_success() {
+                debug!(target: "uniV3::fetcher", "tx reverted in sim: {:?}", transaction.hash);
+            }
+            self.state_db.commit(res.state);
+        }
+
+        Ok(())
+    }
+
+    /// moves the block environment to `block_number`, keeping the replayed state
+    pub async fn set_block(&mut self, block_number: u64) -> eyre::Result<()> {
+        let (cfg_env, block_env, _) = self.node.get_evm_env_at(block_number).await?;
+
+        self.env = call_env(cfg_env.clone(), &block_env);
+        self.cfg = cfg_env;
+        self.block_env = block_env;
+
+        Ok(())
+    }
+
+    /// withdrawals are credited after the block's transactions and are not part of any of them
+    fn apply_withdrawals(&mut self, withdrawals: &[Withdrawal]) -> eyre::Result<()> {
+        for withdrawal in withdrawals {
+            let mut info = self.state_db.basic(withdrawal.address)?.unwrap_or_default();
+            info.balance += U256::from(withdrawal.amount) * U256::from(GWEI_TO_WEI);
+            self.state_db.insert_account_info(withdrawal.address, info);
+        }
+
+        Ok(())
+    }
+
+    /// the system call each block starts with since Cancun, storing the parent beacon block
+    /// root in the EIP-4788 contract
+    pub fn apply_beacon_root(&mut self, header: &Header) -> eyre::Result<()> {
+        let Some(root) = header.parent_beacon_block_root else {
+            return Ok(());
+        };
+
+        for (slot, value) in beacon_root_slots(header.timestamp, root) {
+            self.state_db
+                .insert_account_storage(BEACON_ROOTS_ADDRESS, slot, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// replays the transaction with the block's basefee, so gas is charged like on chain
+    fn replay_transaction(
+        &mut self,
+        transaction: &TransactionSignedEcRecovered,
+    ) -> eyre::Result<ResultAndState> {
+        let env = EnvWithHandlerCfg::new_with_cfg_env(
+            self.cfg.clone(),
+            self.block_env.clone(),
+            tx_env_with_recovered(transaction),
+        );
+
+        self.node
+            .reth_api
+            .eth_api
+            .transact(&mut self.state_db, env)
+            .map(|(res, _)| res)
+            .map_err(|e| {
+                eyre::ErrReport::msg(format!(
+                    "failed to replay tx {:?} - {:?}",
+                    transaction.hash, e
+                ))
+            })
+    }
+}
+
+/// the EIP-4788 contract the parent beacon block roots are stored in
+const BEACON_ROOTS_ADDRESS: Address = address!("000F3df6D732807Ef1319fB7B8bB8522d0Beac02");
+
+/// the number of roots the EIP-4788 contract keeps
+const BEACON_ROOTS_HISTORY_LENGTH: u64 = 8191;
+
+/// the storage the EIP-4788 contract writes for a block, its timestamp and then the root in
+/// the ring buffer right after the timestamps'
+fn beacon_root_slots(timestamp: u64, root: B256) -> [(U256, U256); 2] {
+    let timestamp_slot = timestamp % BEACON_ROOTS_HISTORY_LENGTH;
+
+    [
+        (U256::from(timestamp_slot), U256::from(timestamp)),
+        (
+            U256::from(timestamp_slot + BEACON_ROOTS_HISTORY_LENGTH),
+            U256::from_be_bytes(root.0),
+        ),
+    ]
+}
+
+/// calls are made without a gas price, so they run with a zero basefee
+fn call_env(cfg: CfgEnvWithHandlerCfg, block_env: &BlockEnv) -> EnvWithHandlerCfg {
+    let mut block_env = block_env.clone();
+    block_env.basefee = U256::ZERO;
+
+    EnvWithHandlerCfg::new_with_cfg_env(cfg, block_env, Default::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beacon_root_slots() {
+        let root = B256::with_last_byte(7);
+        let timestamp = 1_710_338_135;
+
+        assert_eq!(
+            beacon_root_slots(timestamp, root),
+            [
+                (U256::from(timestamp % 8191), U256::from(timestamp)),
+                (U256::from(timestamp % 8191 + 8191), U256::from(7))
+            ]
+        );
+    }
 }
@@ -1,9 +1,15 @@
 mod contracts;
 pub use contracts::*;
 
+mod context;
+pub use context::*;
+
 mod fetcher;
 pub use fetcher::*;
 
+mod range;
+pub use range::*;
+
 pub mod types;
 
 mod ticks;
@@ -15,6 +21,78 @@ pub use slot0::*;
 mod trades;
 pub use trades::*;
 
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{checkpoint::Checkpoint, db::InitialPools, utils::TokenInfo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum FetcherKind {
+    Slot0,
+    TickInfo,
+    Trades,
+}
+
+impl FetcherKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FetcherKind::Slot0 => "slot0",
+            FetcherKind::TickInfo => "tick_info",
+            FetcherKind::Trades => "trades",
+        }
+    }
+
+    /// builds the fetcher starting at `earliest_block`, which is at or after the pool's creation
+    pub fn build(&self, pool: &InitialPools, earliest_block: u64) -> Arc<Box<dyn PoolFetcher>> {
+        let token0 = TokenInfo::new(pool.token0_address, pool.token0_decimals);
+        let token1 = TokenInfo::new(pool.token1_address, pool.token1_decimals);
+
+        let fetcher: Box<dyn PoolFetcher> = match self {
+            FetcherKind::Slot0 => Box::new(PoolSlot0Fetcher::new(
+                pool.pool_address,
+                token0,
+                token1,
+                earliest_block,
+            )),
+            FetcherKind::TickInfo => {
+                Box::new(PoolTickFetcher::new(pool.pool_address, earliest_block))
+            }
+            FetcherKind::Trades => Box::new(PoolTradeFetcher::new(
+                pool.pool_address,
+                token0,
+                token1,
+                earliest_block,
+            )),
+        };
+
+        Arc::new(fetcher)
+    }
+}
+
+/// a pool and the fetchers to run for it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedPool {
+    pub pool: InitialPools,
+    pub fetchers: Vec<FetcherKind>,
+}
+
+impl TrackedPool {
+    pub fn new(pool: InitialPools, fetchers: Vec<FetcherKind>) -> Self {
+        Self { pool, fetchers }
+    }
+
+    /// each fetcher starts from its checkpoint, or the pool's creation block if it has none
+    pub fn build_fetchers(&self, checkpoint: &Checkpoint) -> Vec<Arc<Box<dyn PoolFetcher>>> {
+        self.fetchers
+            .iter()
+            .map(|kind| kind.build(&self.pool, checkpoint.start_block(self, *kind)))
+            .collect()
+    }
+}
+
 pub trait PoolFetcher: Send + Sync {
     fn is_re_executed(&self) -> bool;
     fn is_decoded(&self) -> bool;
@@ -37,6 +115,8 @@ pub trait PoolFetcher: Send + Sync {
         unreachable!()
     }
 
+    fn kind(&self) -> FetcherKind;
+
     fn earliest_block(&self) -> u64;
 
     fn pool_address(&self) -> alloy_primitives::Address;
@@ -0,0 +1,265 @@
+use std::{
+    ops::RangeInclusive,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use alloy_primitives::B256;
+use tracing::debug;
+
+use super::{ChangeDetection, CompletedBlock, PoolCaller, PoolDBInner, PoolFetcher};
+use crate::{
+    backlog::PoolDataSender, execute_on_threadpool, node::EthNodeApi, pools::types::PoolBlockData,
+};
+
+pub struct CompletedRange {
+    pub start_block: u64,
+    pub end_block: u64,
+    /// the number of pool fetchers the range was run for
+    pub tasks: usize,
+    /// blocks where none of the pools emitted a log
+    pub skipped_blocks: u64,
+    /// the hash of each block the values were produced from
+    pub block_hashes: Vec<(u64, B256)>,
+    /// how long the range took to run, excluding any retry delay
+    pub elapsed: Duration,
+}
+
+impl CompletedRange {
+    pub fn blocks(&self) -> u64 {
+        self.end_block - self.start_block + 1
+    }
+
+    fn add_block(&mut self, block: CompletedBlock) {
+        self.skipped_blocks += block.skipped as u64;
+        self.block_hashes
+            .push((block.block_number, block.block_hash));
+    }
+}
+
+/// a range that failed at its first block in `blocks`
+pub struct FailedRange {
+    /// the blocks before the failed one, already sent to the writer
+    pub completed: Option<CompletedRange>,
+    /// the failed block and the rest of the range
+    pub blocks: RangeInclusive<u64>,
+    pub error: eyre::ErrReport,
+}
+
+/// the first post-merge block, the carried state doesn't credit the block and uncle rewards
+/// paid before it
+pub const PARIS_BLOCK: u64 = 15537394;
+
+/// the last block of the range starting at `start_block`, pre-merge blocks are run one at a
+/// time from their parent's state
+pub fn range_end(start_block: u64, range_size: u64, end_block: u64) -> u64 {
+    if start_block < PARIS_BLOCK {
+        return start_block;
+    }
+
+    (start_block + range_size.max(1) - 1).min(end_block)
+}
+
+/// walks a contiguous block range in order, replaying each block on top of the state left
+/// by the previous one so the historical state read from the db is cached across blocks
+///
+/// a block with no logs from the pools is not replayed, the next block starts over from the
+/// state at its parent
+pub struct PoolRangeCaller {
+    pub node: Arc<EthNodeApi>,
+    pub db_tx: PoolDataSender,
+    pub pools: Vec<Arc<Box<dyn PoolFetcher>>>,
+    pub start_block: u64,
+    pub end_block: u64,
+    pub change_detection: ChangeDetection,
+}
+
+impl PoolRangeCaller {
+    pub fn new(
+        node: Arc<EthNodeApi>,
+        db_tx: PoolDataSender,
+        pools: &[Arc<Box<dyn PoolFetcher>>],
+        blocks: RangeInclusive<u64>,
+        change_detection: ChangeDetection,
+    ) -> Self {
+        let pools = pools
+            .iter()
+            .filter(|pool| pool.earliest_block() <= *blocks.end())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        Self {
+            node,
+            db_tx,
+            pools,
+            start_block: *blocks.start(),
+            end_block: *blocks.end(),
+            change_detection,
+        }
+    }
+
+    /// on failure returns the blocks of the range that were completed and the ones that were not
+    pub async fn execute_range(self) -> Result<CompletedRange, FailedRange> {
+        let started = Instant::now();
+        let mut completed = CompletedRange {
+            start_block: self.start_block,
+            end_block: self.end_block,
+            tasks: self.pools.len(),
+            skipped_blocks: 0,
+            block_hashes: Vec::new(),
+            elapsed: Duration::ZERO,
+        };
+
+        // a single block has no state to carry, so the pools run in parallel from its parent
+        if self.start_block == self.end_block {
+            let caller = self.block_caller(self.start_block);
+            let block = caller
+                .execute_block()
+                .await
+                .map_err(|(b, error)| FailedRange {
+                    completed: None,
+                    blocks: b..=b,
+                    error,
+                })?;
+            completed.add_block(block);
+            completed.elapsed = started.elapsed();
+
+            return Ok(completed);
+        }
+
+        let mut state = None;
+        for block_number in self.start_block..=self.end_block {
+            let block = match self.execute_block(block_number, &mut state).await {
+                Ok(block) => block,
+                Err(error) => {
+                    completed.end_block = block_number - 1;
+                    completed.elapsed = started.elapsed();
+
+                    return Err(FailedRange {
+                        completed: (block_number > self.start_block).then_some(completed),
+                        blocks: block_number..=self.end_block,
+                        error,
+                    });
+                }
+            };
+            completed.add_block(block);
+        }
+        completed.elapsed = started.elapsed();
+
+        Ok(completed)
+    }
+
+    async fn execute_block(
+        &self,
+        block_number: u64,
+        state: &mut Option<PoolDBInner>,
+    ) -> eyre::Result<CompletedBlock> {
+        let mut caller = self.block_caller(block_number);
+        let tasks = caller.pools.len();
+
+        let header = caller.retain_touched_pools()?;
+        if caller.pools.is_empty() {
+            *state = None;
+            caller.send_skipped_block(&header, tasks)?;
+            return Ok(CompletedBlock::new(
+                block_number,
+                header.hash_slow(),
+                tasks,
+                true,
+            ));
+        }
+
+        let ctx = caller.block_context().await?;
+
+        // only the re-executed fetchers read the state
+        if !caller.pools.iter().any(|pool| pool.is_re_executed()) {
+            *state = None;
+            let decoded = caller.decode_block(&ctx).await?;
+            self.db_tx
+                .send(PoolBlockData::new(block_number, ctx.timestamp(), decoded).into())?;
+
+            return Ok(CompletedBlock::new(
+                block_number,
+                ctx.header.hash(),
+                tasks,
+                false,
+            ));
+        }
+
+        let mut inner = match state.take() {
+            Some(mut inner) => {
+                inner.set_block(block_number).await?;
+                inner
+            }
+            None => {
+                debug!(target: "uniV3::fetcher", "loading state at block {} for range {} - {}", block_number - 1, self.start_block, self.end_block);
+                PoolDBInner::new(self.node.clone(), block_number).await?
+            }
+        };
+
+        let addresses = caller
+            .pools
+            .iter()
+            .filter(|pool| pool.is_re_executed())
+            .map(|pool| pool.pool_address())
+            .collect::<Vec<_>>();
+        let pool_txs = caller.re_executed_pool_txs(addresses, &ctx.traces)?;
+
+        let (re_executed, decoded) = tokio::try_join!(
+            async { execute_on_threadpool(|| inner.replay_block(&ctx, &pool_txs)) },
+            caller.decode_block(&ctx)
+        )?;
+        *state = Some(inner);
+
+        self.db_tx.send(
+            PoolBlockData::new(
+                block_number,
+                ctx.timestamp(),
+                re_executed.into_iter().chain(decoded).collect(),
+            )
+            .into(),
+        )?;
+
+        Ok(CompletedBlock::new(
+            block_number,
+            ctx.header.hash(),
+            tasks,
+            false,
+        ))
+    }
+
+    fn block_caller(&self, block_number: u64) -> PoolCaller {
+        PoolCaller::new(
+            self.node.clone(),
+            self.db_tx.clone(),
+            &self.pools,
+            block_number,
+            self.change_detection,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_end() {
+        assert_eq!(
+            range_end(PARIS_BLOCK - 10, 100, PARIS_BLOCK + 1000),
+            PARIS_BLOCK - 10
+        );
+        assert_eq!(
+            range_end(PARIS_BLOCK, 100, PARIS_BLOCK + 1000),
+            PARIS_BLOCK + 99
+        );
+        assert_eq!(
+            range_end(PARIS_BLOCK + 950, 100, PARIS_BLOCK + 1000),
+            PARIS_BLOCK + 1000
+        );
+        assert_eq!(
+            range_end(PARIS_BLOCK + 5, 1, PARIS_BLOCK + 1000),
+            PARIS_BLOCK + 5
+        );
+    }
+}
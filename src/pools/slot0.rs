@@ -11,6 +11,7 @@ use malachite::Natural;
 use malachite::Rational;
 use tracing::debug;
 
+use super::FetcherKind;
 use super::PoolDBInner;
 use super::PoolFetcher;
 use crate::pools::types::PoolData;
@@ -88,6 +89,10 @@ impl PoolFetcher for PoolSlot0Fetcher {
         Ok(vec![data.into()])
     }
 
+    fn kind(&self) -> FetcherKind {
+        FetcherKind::Slot0
+    }
+
     fn earliest_block(&self) -> u64 {
         self.earliest_block
     }
@@ -3,6 +3,7 @@ use alloy_primitives::TxHash;
 use alloy_primitives::U256;
 use tracing::debug;
 
+use super::FetcherKind;
 use super::PoolDBInner;
 use super::PoolFetcher;
 use crate::pools::types::PoolData;
@@ -114,6 +115,10 @@ impl PoolFetcher for PoolTickFetcher {
         Ok(state.into_iter().map(Into::into).collect())
     }
 
+    fn kind(&self) -> FetcherKind {
+        FetcherKind::TickInfo
+    }
+
     fn earliest_block(&self) -> u64 {
         self.earliest_block
     }
@@ -1,3 +1,4 @@
+use super::FetcherKind;
 use super::PoolFetcher;
 use crate::node::FilteredTraceCall;
 
@@ -78,6 +79,10 @@ impl PoolFetcher for PoolTradeFetcher {
         Ok(data)
     }
 
+    fn kind(&self) -> FetcherKind {
+        FetcherKind::Trades
+    }
+
     fn earliest_block(&self) -> u64 {
         self.earliest_block
     }
@@ -141,7 +146,7 @@ mod tests {
             .unwrap();
 
         let pool_traces = block_traces
-            .into_iter()
+            .iter()
             .flat_map(|trace| filter_traces_by_address_to_call_input(trace, &[pool_address]))
             .into_group_map();
 
@@ -192,26 +192,134 @@ impl PoolTrade {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Row, PartialEq)]
+pub struct PoolCandle {
+    #[serde(with = "serde_address")]
+    pub pool_address: Address,
+    pub interval: String,
+    /// block number for `block` candles, unix timestamp otherwise
+    pub bucket_start: u64,
+    pub start_block: u64,
+    pub end_block: u64,
+    #[serde(with = "serde_address")]
+    pub token0: Address,
+    #[serde(with = "serde_address")]
+    pub token1: Address,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume_token0: f64,
+    pub volume_token1: f64,
+    pub trade_count: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum PoolData {
     TickInfo(PoolTickInfo),
     Slot0(PoolSlot0),
     Trade(PoolTrade),
+    Candle(PoolCandle),
 }
 
 impl PoolData {
-    pub fn combine_many(values: Vec<Self>) -> (Vec<PoolTickInfo>, Vec<PoolSlot0>, Vec<PoolTrade>) {
+    pub fn combine_many(
+        values: Vec<Self>,
+    ) -> (
+        Vec<PoolTickInfo>,
+        Vec<PoolSlot0>,
+        Vec<PoolTrade>,
+        Vec<PoolCandle>,
+    ) {
         let mut tick_info = Vec::new();
         let mut slot0 = Vec::new();
         let mut trades = Vec::new();
+        let mut candles = Vec::new();
 
         values.into_iter().for_each(|v| match v {
             PoolData::TickInfo(val) => tick_info.push(val),
             PoolData::Slot0(val) => slot0.push(val),
             PoolData::Trade(trade) => trades.push(trade),
+            PoolData::Candle(candle) => candles.push(candle),
         });
 
-        (tick_info, slot0, trades)
+        (tick_info, slot0, trades, candles)
+    }
+
+    /// in-memory size of the value, including its heap allocations
+    pub fn approx_size(&self) -> usize {
+        let heap = match self {
+            PoolData::Candle(val) => val.interval.capacity(),
+            _ => 0,
+        };
+
+        std::mem::size_of::<Self>() + heap
+    }
+
+    pub fn pool_address(&self) -> Address {
+        match self {
+            PoolData::TickInfo(val) => val.pool_address,
+            PoolData::Slot0(val) => val.pool_address,
+            PoolData::Trade(val) => val.pool_address,
+            PoolData::Candle(val) => val.pool_address,
+        }
+    }
+
+    /// the last block the value depends on
+    pub fn block_number(&self) -> u64 {
+        match self {
+            PoolData::TickInfo(val) => val.block_number,
+            PoolData::Slot0(val) => val.block_number,
+            PoolData::Trade(val) => val.block_number,
+            PoolData::Candle(val) => val.end_block,
+        }
+    }
+}
+
+/// all values produced for a single block
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolBlockData {
+    pub block_number: u64,
+    pub block_timestamp: u64,
+    pub data: Vec<PoolData>,
+    /// the block was given up on, so it has no values and is never checkpointed
+    pub failed: bool,
+}
+
+impl PoolBlockData {
+    pub fn new(block_number: u64, block_timestamp: u64, data: Vec<PoolData>) -> Self {
+        Self {
+            block_number,
+            block_timestamp,
+            data,
+            failed: false,
+        }
+    }
+
+    pub fn failed(block_number: u64, block_timestamp: u64) -> Self {
+        Self {
+            block_number,
+            block_timestamp,
+            data: Vec::new(),
+            failed: true,
+        }
+    }
+}
+
+/// sent from the fetchers to the db
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoolUpdate {
+    Block(PoolBlockData),
+    /// every block from `from_block` was reorged out, so its values are retracted before the
+    /// blocks are processed again
+    Reorg {
+        from_block: u64,
+    },
+}
+
+impl From<PoolBlockData> for PoolUpdate {
+    fn from(value: PoolBlockData) -> Self {
+        PoolUpdate::Block(value)
     }
 }
 
@@ -231,4 +339,21 @@ macro_rules! to_pool_data {
     };
 }
 
-to_pool_data!(Slot0, TickInfo, Trade);
+to_pool_data!(Slot0, TickInfo, Trade, Candle);
+
+/// a trade of `amount` in pool `0x..01`, shared by the tests of the writer and sinks
+#[cfg(test)]
+pub(crate) fn trade(block_number: u64, amount: i64) -> PoolData {
+    PoolData::Trade(PoolTrade {
+        block_number,
+        tx_hash: TxHash::with_last_byte(1),
+        pool_address: Address::with_last_byte(1),
+        token_in: Address::with_last_byte(2),
+        token_in_decimals: 18,
+        token_in_amount: I256::try_from(amount).unwrap(),
+        token_out: Address::with_last_byte(3),
+        token_out_decimals: 6,
+        token_out_amount: I256::try_from(-amount).unwrap(),
+        calculated_price: 1.0,
+    })
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::BTreeSet,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicU64, Ordering},
+    task::Context,
+    time::{Duration, Instant},
+};
+
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::info;
+
+use crate::pools::types::PoolData;
+
+/// the number of values of each data type produced so far
+#[derive(Debug, Default)]
+pub struct RowCounts {
+    tick_info: AtomicU64,
+    slot0: AtomicU64,
+    trades: AtomicU64,
+    candles: AtomicU64,
+}
+
+impl RowCounts {
+    pub fn add(&self, values: &[PoolData]) {
+        for value in values {
+            let counter = match value {
+                PoolData::TickInfo(_) => &self.tick_info,
+                PoolData::Slot0(_) => &self.slot0,
+                PoolData::Trade(_) => &self.trades,
+                PoolData::Candle(_) => &self.candles,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// tick info, slot0, trades and candles
+    pub fn snapshot(&self) -> [u64; 4] {
+        [
+            self.tick_info.load(Ordering::Relaxed),
+            self.slot0.load(Ordering::Relaxed),
+            self.trades.load(Ordering::Relaxed),
+            self.candles.load(Ordering::Relaxed),
+        ]
+    }
+}
+
+/// the writer's insert retries and the blocks whose values it gave up on
+#[derive(Debug, Default)]
+pub struct WriteCounts {
+    insert_retries: AtomicU64,
+    dead_lettered_blocks: AtomicU64,
+}
+
+impl WriteCounts {
+    pub fn retried(&self) {
+        self.insert_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn gave_up(&self, blocks: usize) {
+        self.dead_lettered_blocks
+            .fetch_add(blocks as u64, Ordering::Relaxed);
+    }
+
+    /// insert retries and dead-lettered blocks
+    pub fn snapshot(&self) -> [u64; 2] {
+        [
+            self.insert_retries.load(Ordering::Relaxed),
+            self.dead_lettered_blocks.load(Ordering::Relaxed),
+        ]
+    }
+}
+
+/// periodically logs how far the run is and how fast it's going
+pub struct ProgressReporter {
+    interval: Interval,
+    started: Instant,
+    start_block: u64,
+    /// the first block not yet completed or given up on, every block before it is
+    next_block: u64,
+    pending_blocks: BTreeSet<u64>,
+    completed_blocks: u64,
+    /// blocks given up on after their fetch ran out of attempts
+    dead_letters: u64,
+    pub fetch_retries: u64,
+    last_report: Instant,
+    last_completed_blocks: u64,
+    last_rows: [u64; 4],
+}
+
+impl ProgressReporter {
+    pub fn new(start_block: u64, report_interval: Duration) -> Self {
+        let mut interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + report_interval,
+            report_interval,
+        );
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let now = Instant::now();
+        Self {
+            interval,
+            started: now,
+            start_block,
+            next_block: start_block,
+            pending_blocks: BTreeSet::new(),
+            completed_blocks: 0,
+            dead_letters: 0,
+            fetch_retries: 0,
+            last_report: now,
+            last_completed_blocks: 0,
+            last_rows: [0; 4],
+        }
+    }
+
+    /// the last block of the contiguous run of completed or given up blocks, if any
+    pub fn completed_block(&self) -> Option<u64> {
+        (self.next_block > self.start_block).then(|| self.next_block - 1)
+    }
+
+    pub fn complete(&mut self, blocks: RangeInclusive<u64>) {
+        self.completed_blocks += blocks.end() - blocks.start() + 1;
+        self.advance(blocks);
+    }
+
+    /// moves past a block whose fetch ran out of attempts without counting it as completed
+    pub fn give_up(&mut self, block_number: u64) {
+        self.dead_letters += 1;
+        self.advance(block_number..=block_number);
+    }
+
+    fn advance(&mut self, blocks: RangeInclusive<u64>) {
+        self.pending_blocks.extend(blocks);
+        while self.pending_blocks.remove(&self.next_block) {
+            self.next_block += 1;
+        }
+    }
+
+    /// forgets the reorged blocks so they're counted again when they're re-processed
+    pub fn rewind(&mut self, from_block: u64) {
+        self.pending_blocks.split_off(&from_block);
+        self.next_block = self.next_block.min(from_block.max(self.start_block));
+    }
+
+    /// estimated time to complete every block up to `end_block` at the average rate of the run
+    pub fn eta(&self, end_block: u64) -> Option<Duration> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if self.completed_blocks == 0 || elapsed == 0.0 {
+            return None;
+        }
+
+        let remaining = (end_block + 1).saturating_sub(self.next_block);
+        let blocks_per_sec = self.completed_blocks as f64 / elapsed;
+        Some(Duration::from_secs_f64(remaining as f64 / blocks_per_sec))
+    }
+
+    /// logs the progress since the last report on each tick of the interval
+    pub fn poll_report(
+        &mut self,
+        cx: &mut Context<'_>,
+        in_flight_blocks: u64,
+        end_block: u64,
+        rows: &RowCounts,
+        writes: &WriteCounts,
+    ) {
+        if self.interval.poll_tick(cx).is_pending() {
+            return;
+        }
+
+        let now = Instant::now();
+        let elapsed = now
+            .duration_since(self.last_report)
+            .as_secs_f64()
+            .max(f64::EPSILON);
+        let rows = rows.snapshot();
+        let [tick_info, slot0, trades, candles] =
+            std::array::from_fn(|i| (rows[i] - self.last_rows[i]) as f64 / elapsed);
+        let blocks_per_sec = (self.completed_blocks - self.last_completed_blocks) as f64 / elapsed;
+        let [insert_retries, insert_dead_letters] = writes.snapshot();
+
+        info!(target: "uniV3::progress", "completed up to block {} of {end_block}, {in_flight_blocks} in flight, {:.1} blocks/s, {:.0} tick info/s, {:.0} slot0/s, {:.0} trades/s, {:.0} candles/s, {} fetch retries, {} insert retries, gave up fetching {} blocks and inserting {} blocks, eta {}", self.completed_block().map_or_else(|| "none".to_string(), |block| block.to_string()), blocks_per_sec, tick_info, slot0, trades, candles, self.fetch_retries, insert_retries, self.dead_letters, insert_dead_letters, format_eta(self.eta(end_block)));
+
+        self.last_report = now;
+        self.last_completed_blocks = self.completed_blocks;
+        self.last_rows = rows;
+    }
+}
+
+fn format_eta(eta: Option<Duration>) -> String {
+    let Some(eta) = eta else {
+        return "unknown".to_string();
+    };
+
+    let secs = eta.as_secs();
+    format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_progress_completes_contiguously() {
+        let mut progress = ProgressReporter::new(100, Duration::from_secs(10));
+        assert_eq!(progress.completed_block(), None);
+
+        progress.complete(102..=103);
+        assert_eq!(progress.completed_block(), None);
+
+        progress.complete(100..=101);
+        assert_eq!(progress.completed_block(), Some(103));
+
+        progress.complete(105..=105);
+        progress.rewind(103);
+        assert_eq!(progress.completed_block(), Some(102));
+
+        progress.complete(103..=104);
+        assert_eq!(progress.completed_block(), Some(104));
+
+        // a block given up on moves past it without counting as completed
+        progress.give_up(105);
+        progress.complete(106..=106);
+        assert_eq!(progress.completed_block(), Some(106));
+        assert_eq!((progress.completed_blocks, progress.dead_letters), (8, 1));
+    }
+
+    #[test]
+    fn test_format_eta() {
+        assert_eq!(format_eta(Some(Duration::from_secs(3725))), "1h02m05s");
+        assert_eq!(format_eta(None), "unknown");
+    }
+}
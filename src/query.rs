@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use alloy_primitives::{Address, TxHash, U256};
+use clap::Args;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use tracing::info;
+
+use crate::{
+    node::EthNodeApi,
+    pools::{
+        types::{PoolData, PoolSlot0, PoolTickInfo},
+        BlockContext, PoolDBInner, PoolFetcher, PoolSlot0Fetcher, PoolTickFetcher,
+    },
+    sinks::text::{decimal, hex, JsonFields, TextRow},
+    utils::TokenInfo,
+};
+
+/// a pool and the point in a block to read its state at
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct PoolStateQuery {
+    /// the pool to read
+    #[arg(long)]
+    pub pool: Address,
+
+    /// the block the state is read in, replayed on top of its parent so never the genesis block
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub block: u64,
+
+    /// reads the state right after this transaction of the block instead of at its end
+    #[arg(long, conflicts_with = "tx_index")]
+    pub tx_hash: Option<TxHash>,
+
+    /// reads the state right after the transaction at this index of the block instead of at
+    /// its end
+    #[arg(long)]
+    pub tx_index: Option<u64>,
+}
+
+/// the pool-wide values that aren't part of `slot0()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolGlobals {
+    pub token0: TokenInfo,
+    pub token1: TokenInfo,
+    pub fee: u32,
+    pub tick_spacing: i32,
+    pub liquidity: u128,
+    pub fee_growth_global_0_x128: U256,
+    pub fee_growth_global_1_x128: U256,
+    pub protocol_fees_token0: u128,
+    pub protocol_fees_token1: u128,
+}
+
+/// a pool's state right after a transaction, or at the start of a block that has none
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolState {
+    pub pool_address: Address,
+    pub block_number: u64,
+    /// the last replayed transaction, `None` if the block has none
+    pub tx_hash: Option<TxHash>,
+    pub tx_index: Option<u64>,
+    pub slot0: PoolSlot0,
+    pub globals: PoolGlobals,
+    /// every initialized tick
+    pub ticks: Vec<PoolTickInfo>,
+}
+
+/// the json of the `query` subcommand, with the 128 and 256 bit integers as decimal strings like
+/// the `jsonl` sink
+impl Serialize for PoolState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let globals = [
+            ("token0", hex(self.globals.token0.address)),
+            ("token0_decimals", self.globals.token0.decimals.into()),
+            ("token1", hex(self.globals.token1.address)),
+            ("token1_decimals", self.globals.token1.decimals.into()),
+            ("fee", self.globals.fee.into()),
+            ("tick_spacing", self.globals.tick_spacing.into()),
+            ("liquidity", decimal(self.globals.liquidity)),
+            (
+                "fee_growth_global_0_x128",
+                decimal(self.globals.fee_growth_global_0_x128),
+            ),
+            (
+                "fee_growth_global_1_x128",
+                decimal(self.globals.fee_growth_global_1_x128),
+            ),
+            (
+                "protocol_fees_token0",
+                decimal(self.globals.protocol_fees_token0),
+            ),
+            (
+                "protocol_fees_token1",
+                decimal(self.globals.protocol_fees_token1),
+            ),
+        ];
+        let slot0 = self.slot0.fields();
+        let ticks = self
+            .ticks
+            .iter()
+            .map(|tick| tick.fields())
+            .collect::<Vec<_>>();
+
+        let mut map = serializer.serialize_map(Some(7))?;
+        map.serialize_entry("pool_address", &hex(self.pool_address))?;
+        map.serialize_entry("block_number", &self.block_number)?;
+        map.serialize_entry("tx_hash", &self.tx_hash.map(hex))?;
+        map.serialize_entry("tx_index", &self.tx_index)?;
+        map.serialize_entry("slot0", &JsonFields(&slot0))?;
+        map.serialize_entry("globals", &JsonFields(&globals))?;
+        map.serialize_entry(
+            "ticks",
+            &ticks
+                .iter()
+                .map(|tick| JsonFields(tick.as_slice()))
+                .collect::<Vec<_>>(),
+        )?;
+        map.end()
+    }
+}
+
+/// replays the block up to the queried transaction and reads the pool's slot0, globals and
+/// ticks from the resulting state, without writing anything
+pub async fn query_pool_state(
+    node: Arc<EthNodeApi>,
+    query: &PoolStateQuery,
+) -> eyre::Result<PoolState> {
+    let ctx = BlockContext::new(&node, query.block, false).await?;
+    let hashes = ctx
+        .transactions
+        .iter()
+        .map(|transaction| transaction.hash)
+        .collect::<Vec<_>>();
+    let tx_index = target_tx_index(&hashes, query.tx_hash, query.tx_index)?;
+
+    let mut inner = PoolDBInner::new(node, query.block).await?;
+    if let Some(tx_index) = tx_index {
+        info!(target: "uniV3::query", "replaying {} of {} transactions in block {}", tx_index + 1, hashes.len(), query.block);
+        inner.replay_transactions(&ctx.transactions[..=tx_index])?;
+    }
+
+    let (token0, token1) = inner.get_pool_tokens(query.pool)?;
+    let token0 = TokenInfo::new(token0, inner.get_token_decimals(token0)?);
+    let token1 = TokenInfo::new(token1, inner.get_token_decimals(token1)?);
+    let (fee_growth_global_0_x128, fee_growth_global_1_x128) =
+        inner.get_fee_growth_globals(query.pool)?;
+    let (protocol_fees_token0, protocol_fees_token1) = inner.get_protocol_fees(query.pool)?;
+    let globals = PoolGlobals {
+        fee: inner.get_fee(query.pool)?,
+        tick_spacing: inner.get_tick_spacing(query.pool)?,
+        liquidity: inner.get_liquidity(query.pool)?,
+        fee_growth_global_0_x128,
+        fee_growth_global_1_x128,
+        protocol_fees_token0,
+        protocol_fees_token1,
+        token0: token0.clone(),
+        token1: token1.clone(),
+    };
+
+    // the rows are labeled with the replayed transaction, or an empty one at the block's start
+    let tx_hash = tx_index.map(|i| hashes[i]);
+    let (row_tx_hash, row_tx_index) = (tx_hash.unwrap_or_default(), tx_index.unwrap_or(0) as u64);
+
+    let slot0 = PoolSlot0Fetcher::new(query.pool, token0, token1, query.block)
+        .re_execute_block(&mut inner, query.block, row_tx_hash, row_tx_index)?
+        .into_iter()
+        .find_map(|value| match value {
+            PoolData::Slot0(slot0) => Some(slot0),
+            _ => None,
+        })
+        .ok_or(eyre::ErrReport::msg(format!(
+            "no slot0 for pool {:?} in block {}",
+            query.pool, query.block
+        )))?;
+
+    let ticks = PoolTickFetcher::new(query.pool, query.block)
+        .re_execute_block(&mut inner, query.block, row_tx_hash, row_tx_index)?
+        .into_iter()
+        .filter_map(|value| match value {
+            PoolData::TickInfo(tick) => Some(tick),
+            _ => None,
+        })
+        .collect();
+
+    Ok(PoolState {
+        pool_address: query.pool,
+        block_number: query.block,
+        tx_hash,
+        tx_index: tx_index.map(|i| i as u64),
+        slot0,
+        globals,
+        ticks,
+    })
+}
+
+/// the index of the last transaction to replay, the block's last one if none is given
+fn target_tx_index(
+    hashes: &[TxHash],
+    tx_hash: Option<TxHash>,
+    tx_index: Option<u64>,
+) -> eyre::Result<Option<usize>> {
+    match (tx_hash, tx_index) {
+        (Some(tx_hash), _) => hashes
+            .iter()
+            .position(|hash| *hash == tx_hash)
+            .map(Some)
+            .ok_or(eyre::ErrReport::msg(format!(
+                "transaction {tx_hash:?} is not in the block"
+            ))),
+        (None, Some(tx_index)) => {
+            if (tx_index as usize) < hashes.len() {
+                Ok(Some(tx_index as usize))
+            } else {
+                Err(eyre::ErrReport::msg(format!(
+                    "the block has {} transactions, no index {tx_index}",
+                    hashes.len()
+                )))
+            }
+        }
+        (None, None) => Ok(hashes.len().checked_sub(1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    struct QueryCmd {
+        #[command(flatten)]
+        query: PoolStateQuery,
+    }
+
+    #[test]
+    fn test_rejects_the_genesis_block() {
+        let pool = "0x0000000000000000000000000000000000000001";
+
+        assert!(QueryCmd::try_parse_from(["query", "--pool", pool, "--block", "0"]).is_err());
+        let cmd = QueryCmd::try_parse_from(["query", "--pool", pool, "--block", "1"]).unwrap();
+        assert_eq!(cmd.query.block, 1);
+    }
+
+    #[test]
+    fn test_target_tx_index() {
+        let hashes = (1..=3).map(TxHash::with_last_byte).collect::<Vec<_>>();
+
+        assert_eq!(target_tx_index(&hashes, None, None).unwrap(), Some(2));
+        assert_eq!(target_tx_index(&[], None, None).unwrap(), None);
+        assert_eq!(target_tx_index(&hashes, None, Some(1)).unwrap(), Some(1));
+        assert!(target_tx_index(&hashes, None, Some(3)).is_err());
+        assert_eq!(
+            target_tx_index(&hashes, Some(TxHash::with_last_byte(3)), None).unwrap(),
+            Some(2)
+        );
+        assert!(target_tx_index(&hashes, Some(TxHash::with_last_byte(4)), None).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::metrics;
+
+/// how often failed blocks and inserts are retried before they're given up on
+#[derive(Debug, Clone, PartialEq, Args)]
+#[command(next_help_heading = "Retries")]
+pub struct RetryPolicy {
+    /// attempts for a block or insert before it's recorded as a dead letter and skipped
+    #[arg(long, default_value = "5")]
+    pub max_attempts: u32,
+
+    /// delay before the first retry in milliseconds, doubled on each retry after
+    #[arg(long, default_value = "500")]
+    pub retry_delay_ms: u64,
+
+    /// maximum delay between retries in milliseconds
+    #[arg(long, default_value = "60000")]
+    pub max_retry_delay_ms: u64,
+
+    /// `.jsonl` file the blocks that failed every attempt are appended to
+    #[arg(long, default_value = "dead_letters.jsonl")]
+    pub dead_letters: PathBuf,
+}
+
+impl RetryPolicy {
+    /// whether to retry after `attempts` failed attempts
+    pub fn should_retry(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    /// the delay before retrying after `attempts` failed attempts
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let delay = self
+            .retry_delay_ms
+            .saturating_mul(1 << attempts.saturating_sub(1).min(32));
+
+        Duration::from_millis(delay.min(self.max_retry_delay_ms))
+    }
+
+    /// appends the dead letter, logging instead of failing if the file can't be written
+    pub fn record(&self, dead_letter: DeadLetter) {
+        metrics::record_dead_letter(dead_letter.stage);
+        error!(target: "uniV3", "giving up on blocks {:?} after {} attempts - {}", dead_letter.blocks, dead_letter.attempts, dead_letter.error);
+
+        if let Err(e) = dead_letter.append(&self.dead_letters) {
+            error!(target: "uniV3", "failed to write dead letter to {} - {:?}", self.dead_letters.display(), e);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailedStage {
+    /// running the fetchers for the block
+    Fetch,
+    /// inserting the block's values into the db
+    Insert,
+    /// deleting the values of reorged blocks from the db
+    Retract,
+}
+
+impl FailedStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailedStage::Fetch => "fetch",
+            FailedStage::Insert => "insert",
+            FailedStage::Retract => "retract",
+        }
+    }
+}
+
+/// a failure that was given up on, the blocks need to be re-run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub stage: FailedStage,
+    pub blocks: Vec<u64>,
+    pub attempts: u32,
+    pub error: String,
+}
+
+impl DeadLetter {
+    pub fn new(
+        stage: FailedStage,
+        blocks: Vec<u64>,
+        attempts: u32,
+        error: &eyre::ErrReport,
+    ) -> Self {
+        Self {
+            stage,
+            blocks,
+            attempts,
+            error: format!("{:?}", error),
+        }
+    }
+
+    fn append(&self, path: &Path) -> eyre::Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(file, "{}", serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+}
+
+pub async fn with_delay<F: Future>(delay: Duration, fut: F) -> F::Output {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+
+    fut.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            retry_delay_ms: 500,
+            max_retry_delay_ms: 3000,
+            dead_letters: PathBuf::new(),
+        };
+
+        let delays = (1..=5)
+            .map(|attempts| policy.delay(attempts).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![500, 1000, 2000, 3000, 3000]);
+
+        assert!(policy.should_retry(3));
+        assert!(!policy.should_retry(4));
+    }
+}
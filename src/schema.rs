@@ -0,0 +1,388 @@
+use alloy_primitives::Address;
+use clap::{Args, ValueEnum};
+use clickhouse::Row;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    const_sql::TABLE_COLUMNS,
+    pools::types::{PoolCandle, PoolSlot0, PoolTickInfo, PoolTrade},
+};
+
+/// the `CREATE TABLE` statements the tables are created from, with their database, cluster and
+/// engine replaced by the configured ones
+const TABLES: [(&str, &[&str]); 4] = [
+    (
+        include_str!("sql/tables/tick_info.sql"),
+        PoolTickInfo::COLUMN_NAMES,
+    ),
+    (
+        include_str!("sql/tables/slot0.sql"),
+        PoolSlot0::COLUMN_NAMES,
+    ),
+    (
+        include_str!("sql/tables/trades.sql"),
+        PoolTrade::COLUMN_NAMES,
+    ),
+    (
+        include_str!("sql/tables/candles.sql"),
+        PoolCandle::COLUMN_NAMES,
+    ),
+];
+
+/// where and how the clickhouse tables are created
+#[derive(Debug, Clone, PartialEq, Args)]
+#[command(next_help_heading = "ClickHouse")]
+pub struct ClickhouseSchema {
+    /// database of the `uni_v3_*` tables
+    #[arg(long, default_value = "eth_analytics", global = true)]
+    pub clickhouse_database: String,
+
+    /// cluster the tables are created, altered and retracted on, `none` for a single node
+    #[arg(long, default_value = "eth_cluster0", global = true)]
+    pub clickhouse_cluster: String,
+
+    /// engine the tables are created with
+    #[arg(long, value_enum, default_value_t = TableEngine::Replicated, global = true)]
+    pub clickhouse_engine: TableEngine,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TableEngine {
+    /// `ReplicatedReplacingMergeTree`, needs a cluster
+    Replicated,
+    /// `ReplacingMergeTree`
+    Replacing,
+}
+
+impl ClickhouseSchema {
+    pub fn cluster(&self) -> Option<&str> {
+        (self.clickhouse_cluster != "none").then_some(self.clickhouse_cluster.as_str())
+    }
+
+    fn on_cluster(&self) -> String {
+        self.cluster()
+            .map(|cluster| format!(" ON CLUSTER {cluster}"))
+            .unwrap_or_default()
+    }
+
+    fn engine(&self, table: &str) -> eyre::Result<String> {
+        match self.clickhouse_engine {
+            TableEngine::Replicated => {
+                let cluster = self.cluster().ok_or(eyre::ErrReport::msg(
+                    "the replicated engine needs a cluster",
+                ))?;
+                Ok(format!(
+                    "ReplicatedReplacingMergeTree('/clickhouse/{cluster}/tables/all/{}/{table}', '{{replica}}', `last_updated`)",
+                    self.clickhouse_database
+                ))
+            }
+            TableEngine::Replacing => Ok("ReplacingMergeTree(`last_updated`)".to_string()),
+        }
+    }
+
+    /// deletes the pools' rows from the block given as the query's parameter
+    pub fn retract_query(&self, table: &str, block_column: &str, pools: &[Address]) -> String {
+        format!(
+            "DELETE FROM {}.{table}{} WHERE {block_column} >= ? AND pool_address IN ({})",
+            self.clickhouse_database,
+            self.on_cluster(),
+            pools
+                .iter()
+                .map(|pool| format!("'{:?}'", pool).to_lowercase())
+                .join(", ")
+        )
+    }
+
+    fn create_query(&self, table: &TableSchema) -> eyre::Result<String> {
+        Ok(format!(
+            "CREATE TABLE IF NOT EXISTS {}.{}{}\n(\n{}\n)\nENGINE = {}\nPRIMARY KEY {}\nORDER BY {}",
+            self.clickhouse_database,
+            table.name,
+            self.on_cluster(),
+            table
+                .columns
+                .iter()
+                .map(|column| format!("    `{}` {}", column.name, column.definition))
+                .join(",\n"),
+            self.engine(&table.name)?,
+            table.primary_key,
+            table.order_by
+        ))
+    }
+
+    fn add_column_query(&self, table: &TableSchema, column: &TableColumn) -> String {
+        format!(
+            "ALTER TABLE {}.{}{} ADD COLUMN IF NOT EXISTS `{}` {}",
+            self.clickhouse_database,
+            table.name,
+            self.on_cluster(),
+            column.name,
+            column.definition
+        )
+    }
+}
+
+/// a table of `src/sql/tables`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableSchema {
+    pub name: String,
+    pub columns: Vec<TableColumn>,
+    pub primary_key: String,
+    pub order_by: String,
+    /// the fields of the table's `Row` struct, which are inserted
+    pub row_columns: &'static [&'static str],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableColumn {
+    pub name: String,
+    /// the type and any default
+    pub definition: String,
+}
+
+impl TableColumn {
+    /// the type as it's listed in `system.columns`
+    pub fn column_type(&self) -> &str {
+        self.definition
+            .split_once(" Default ")
+            .map_or(self.definition.as_str(), |(column_type, _)| column_type)
+            .trim()
+    }
+}
+
+impl TableSchema {
+    fn parse(sql: &str, row_columns: &'static [&'static str]) -> eyre::Result<Self> {
+        let mut name = None;
+        let mut columns = Vec::new();
+        let mut primary_key = None;
+        let mut order_by = None;
+
+        for line in sql.lines().map(str::trim) {
+            if let Some(rest) = line.strip_prefix("CREATE TABLE ") {
+                name = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|table| table.rsplit('.').next())
+                    .map(str::to_string);
+            } else if let Some(rest) = line.strip_prefix('`') {
+                let (column, definition) = rest
+                    .split_once('`')
+                    .ok_or(eyre::ErrReport::msg(format!("bad column {line}")))?;
+                columns.push(TableColumn {
+                    name: column.to_string(),
+                    definition: definition.trim().trim_end_matches(',').trim().to_string(),
+                });
+            } else if let Some(rest) = line.strip_prefix("PRIMARY KEY ") {
+                primary_key = Some(rest.to_string());
+            } else if let Some(rest) = line.strip_prefix("ORDER BY ") {
+                order_by = Some(rest.to_string());
+            }
+        }
+
+        Ok(Self {
+            name: name.ok_or(eyre::ErrReport::msg("no table name"))?,
+            columns,
+            primary_key: primary_key.ok_or(eyre::ErrReport::msg("no primary key"))?,
+            order_by: order_by.ok_or(eyre::ErrReport::msg("no order by"))?,
+            row_columns,
+        })
+    }
+
+    /// the differences between the live columns and the table's, empty if they match
+    fn mismatches(&self, live: &[LiveColumn]) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        for column in &self.columns {
+            match live.iter().find(|live| live.name == column.name) {
+                None => mismatches.push(format!("missing column `{}`", column.name)),
+                Some(live) if live.column_type != column.column_type() => mismatches.push(format!(
+                    "column `{}` is {} instead of {}",
+                    column.name,
+                    live.column_type,
+                    column.column_type()
+                )),
+                Some(_) => {}
+            }
+        }
+
+        mismatches
+    }
+}
+
+/// a column of a live table, from `system.columns`
+#[derive(Debug, Clone, Serialize, Deserialize, Row, PartialEq)]
+pub struct LiveColumn {
+    pub name: String,
+    pub column_type: String,
+}
+
+pub fn tables() -> eyre::Result<Vec<TableSchema>> {
+    TABLES
+        .iter()
+        .map(|(sql, row_columns)| TableSchema::parse(sql, row_columns))
+        .collect()
+}
+
+async fn live_columns(
+    db: &clickhouse::Client,
+    schema: &ClickhouseSchema,
+    table: &TableSchema,
+) -> eyre::Result<Vec<LiveColumn>> {
+    Ok(db
+        .query(TABLE_COLUMNS)
+        .bind(schema.clickhouse_database.as_str())
+        .bind(table.name.as_str())
+        .fetch_all()
+        .await?)
+}
+
+/// creates the database and the missing tables, and adds the missing columns to the existing
+/// ones
+///
+/// a column of a different type is never changed, it's reported by the verification after
+pub async fn apply(db: &clickhouse::Client, schema: &ClickhouseSchema) -> eyre::Result<()> {
+    db.query(&format!(
+        "CREATE DATABASE IF NOT EXISTS {}{}",
+        schema.clickhouse_database,
+        schema.on_cluster()
+    ))
+    .execute()
+    .await?;
+
+    for table in tables()? {
+        let live = live_columns(db, schema, &table).await?;
+        if live.is_empty() {
+            info!(target: "uniV3::db", "creating table {}.{}", schema.clickhouse_database, table.name);
+            db.query(&schema.create_query(&table)?).execute().await?;
+            continue;
+        }
+
+        for column in &table.columns {
+            if !live.iter().any(|live| live.name == column.name) {
+                info!(target: "uniV3::db", "adding column `{}` to {}.{}", column.name, schema.clickhouse_database, table.name);
+                db.query(&schema.add_column_query(&table, column))
+                    .execute()
+                    .await?;
+            }
+        }
+    }
+
+    verify(db, schema).await
+}
+
+/// checks that every table exists with the columns of its `CREATE TABLE` statement, so a run
+/// doesn't fail on its first insert
+pub async fn verify(db: &clickhouse::Client, schema: &ClickhouseSchema) -> eyre::Result<()> {
+    let mut errors = Vec::new();
+    for table in tables()? {
+        let live = live_columns(db, schema, &table).await?;
+        let full_name = format!("{}.{}", schema.clickhouse_database, table.name);
+        if live.is_empty() {
+            errors.push(format!("{full_name} doesn't exist"));
+        } else {
+            errors.extend(
+                table
+                    .mismatches(&live)
+                    .into_iter()
+                    .map(|mismatch| format!("{full_name}: {mismatch}")),
+            );
+        }
+    }
+
+    if !errors.is_empty() {
+        eyre::bail!(
+            "the clickhouse tables don't match the schema, run the `schema` subcommand to create or migrate them:\n{}",
+            errors.join("\n")
+        )
+    }
+
+    info!(target: "uniV3::db", "verified the clickhouse tables in {}", schema.clickhouse_database);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single_node() -> ClickhouseSchema {
+        ClickhouseSchema {
+            clickhouse_database: "dev".to_string(),
+            clickhouse_cluster: "none".to_string(),
+            clickhouse_engine: TableEngine::Replacing,
+        }
+    }
+
+    #[test]
+    fn test_tables_match_rows() {
+        for table in tables().unwrap() {
+            let columns = table
+                .columns
+                .iter()
+                .map(|column| column.name.as_str())
+                .filter(|name| *name != "last_updated")
+                .collect::<Vec<_>>();
+            assert_eq!(columns, table.row_columns, "{}", table.name);
+        }
+    }
+
+    #[test]
+    fn test_create_query() {
+        let tables = tables().unwrap();
+        let candles = tables
+            .iter()
+            .find(|table| table.name == "uni_v3_candles")
+            .unwrap();
+        assert_eq!(candles.columns.last().unwrap().column_type(), "UInt64");
+
+        let query = single_node().create_query(candles).unwrap();
+        assert!(query.starts_with("CREATE TABLE IF NOT EXISTS dev.uni_v3_candles\n("));
+        assert!(query.contains("ENGINE = ReplacingMergeTree(`last_updated`)"));
+        assert!(query.ends_with("ORDER BY (`pool_address`, `interval`, `bucket_start`)"));
+
+        let replicated = ClickhouseSchema {
+            clickhouse_engine: TableEngine::Replicated,
+            ..single_node()
+        };
+        assert!(replicated.create_query(candles).is_err());
+
+        assert_eq!(
+            single_node().retract_query(
+                "uni_v3_trades",
+                "block_number",
+                &[Address::with_last_byte(1), Address::with_last_byte(0xab)]
+            ),
+            "DELETE FROM dev.uni_v3_trades WHERE block_number >= ? AND pool_address IN ('0x0000000000000000000000000000000000000001', '0x00000000000000000000000000000000000000ab')"
+        );
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let tables = tables().unwrap();
+        let trades = tables
+            .iter()
+            .find(|table| table.name == "uni_v3_trades")
+            .unwrap();
+
+        let mut live = trades
+            .columns
+            .iter()
+            .map(|column| LiveColumn {
+                name: column.name.clone(),
+                column_type: column.column_type().to_string(),
+            })
+            .collect::<Vec<_>>();
+        assert!(trades.mismatches(&live).is_empty());
+
+        live.pop();
+        live[0].column_type = "UInt32".to_string();
+        assert_eq!(
+            trades.mismatches(&live),
+            vec![
+                "column `block_number` is UInt32 instead of UInt64".to_string(),
+                "missing column `last_updated`".to_string()
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use alloy_primitives::Address;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::pools::types::PoolData;
+
+pub mod parquet;
+pub mod sqlite;
+pub mod tcp;
+pub mod text;
+
+/// a destination for the values produced by the fetchers, written in batches by the
+/// `BufferedWriter`
+///
+/// a batch that fails is retried whole, so writes should be idempotent
+#[async_trait]
+pub trait PoolDataSink: Send + Sync {
+    /// the name the sink is logged and labeled with
+    fn name(&self) -> &'static str;
+
+    async fn insert(&self, values: &[PoolData]) -> eyre::Result<()>;
+
+    /// deletes the values of the pools from every block from `from_block`, they are re-sent
+    /// after a reorg
+    ///
+    /// only the pools of the run are given, the values of any other pool are left alone
+    async fn retract(&self, from_block: u64, pools: &[Address]) -> eyre::Result<()>;
+
+    /// called once after the last batch was written
+    async fn finish(&self) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    /// called with each block's values as soon as the writer receives them, before they're
+    /// batched, for the sinks that stream them as they're produced
+    fn on_block(&self, _values: &[PoolData]) {}
+
+    /// called as soon as the writer receives a reorg, before the queued values are retracted
+    fn on_reorg(&self, _from_block: u64, _pools: &[Address]) {}
+
+    /// called once every block up to `block` was written to every sink or given up on, so no
+    /// more values of those blocks will be inserted
+    fn on_completed(&self, _block: u64) {}
+}
+
+/// the sinks that can be picked from the cli
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum SinkKind {
+    /// the `uni_v3_*` tables of `--clickhouse-database`
+    Clickhouse,
+    /// parquet files partitioned by table, pool and block range
+    Parquet,
+    /// a tagged json object per line, to a file or stdout
+    Jsonl,
+    /// a csv file per table
+    Csv,
+    /// the `uni_v3_*` tables in a local sqlite database
+    Sqlite,
+    /// json lines broadcast to the subscribers of a local tcp socket
+    Tcp,
+}
+
+/// a batch handed to an in-process consumer
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkEvent {
+    Insert(Vec<PoolData>),
+    Retract {
+        from_block: u64,
+        pools: Vec<Address>,
+    },
+}
+
+/// hands every batch to an in-process consumer over a channel
+pub struct ChannelSink {
+    tx: UnboundedSender<SinkEvent>,
+}
+
+impl ChannelSink {
+    pub fn new(tx: UnboundedSender<SinkEvent>) -> Self {
+        Self { tx }
+    }
+}
+
+#[async_trait]
+impl PoolDataSink for ChannelSink {
+    fn name(&self) -> &'static str {
+        "channel"
+    }
+
+    async fn insert(&self, values: &[PoolData]) -> eyre::Result<()> {
+        Ok(self.tx.send(SinkEvent::Insert(values.to_vec()))?)
+    }
+
+    async fn retract(&self, from_block: u64, pools: &[Address]) -> eyre::Result<()> {
+        Ok(self.tx.send(SinkEvent::Retract {
+            from_block,
+            pools: pools.to_vec(),
+        })?)
+    }
+}
@@ -0,0 +1,954 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use alloy_primitives::{Address, I256, U256};
+use arrow::{
+    array::{
+        Array, ArrayRef, BooleanArray, FixedSizeBinaryBuilder, Float64Array, Int32Array,
+        Int64Array, StringArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+    },
+    compute::filter_record_batch,
+    datatypes::{Field, Schema},
+    record_batch::RecordBatch,
+};
+use async_trait::async_trait;
+use clap::{Args, ValueEnum};
+use clickhouse::Row;
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+    basic::Compression,
+    file::properties::WriterProperties,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use super::{text::hex_string, PoolDataSink};
+use crate::pools::types::{PoolCandle, PoolData, PoolSlot0, PoolTickInfo, PoolTrade};
+
+/// where and how the parquet sink writes its files
+#[derive(Debug, Clone, PartialEq, Args)]
+#[command(next_help_heading = "Parquet sink")]
+pub struct ParquetArgs {
+    /// directory the parquet files and their manifest are written to
+    #[arg(long, default_value = "parquet")]
+    pub parquet_dir: PathBuf,
+
+    /// blocks covered by each partition of a pool's files
+    #[arg(long, default_value = "100000")]
+    pub parquet_blocks_per_file: u64,
+
+    /// how the 128 and 256 bit integer columns are stored
+    #[arg(long, value_enum, default_value_t = BigIntEncoding::FixedBinary)]
+    pub parquet_big_ints: BigIntEncoding,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum BigIntEncoding {
+    /// big-endian fixed size binary, two's complement for the signed columns
+    #[default]
+    FixedBinary,
+    /// decimal strings
+    Decimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParquetTable {
+    TickInfo,
+    Slot0,
+    Trades,
+    Candles,
+}
+
+impl ParquetTable {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParquetTable::TickInfo => "tick_info",
+            ParquetTable::Slot0 => "slot0",
+            ParquetTable::Trades => "trades",
+            ParquetTable::Candles => "candles",
+        }
+    }
+
+    /// the column reorgs are retracted by
+    ///
+    /// a file can't replace a row by its key, so a candle that only ends after the reorg is
+    /// dropped too, it's written again once the re-processed blocks close it
+    fn block_column(&self) -> &'static str {
+        match self {
+            ParquetTable::Candles => "end_block",
+            _ => "block_number",
+        }
+    }
+}
+
+/// a written file, its path is relative to the manifest's directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParquetFile {
+    pub path: PathBuf,
+    pub table: ParquetTable,
+    pub pool_address: Address,
+    /// the first and last block of the values in the file
+    pub start_block: u64,
+    pub end_block: u64,
+    pub rows: usize,
+}
+
+/// every file the sink has written, kept in `manifest.json` next to them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParquetManifest {
+    pub files: Vec<ParquetFile>,
+    next_part: u64,
+}
+
+impl ParquetManifest {
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// writes to a temporary file first so a crash mid-write never leaves a partial manifest
+    pub fn save(&self, path: &Path) -> eyre::Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+}
+
+/// the table, pool and partition of a file
+type PartitionKey = (ParquetTable, Address, u64);
+
+/// directory under `--parquet-dir` the parts of the open partitions are staged in
+const STAGING_DIR: &str = ".staging";
+
+/// writes the values to a file per table, pool and block range, partitioned as
+/// `<table>/pool=<address>/blocks=<start>-<end>/part-<n>.parquet`
+///
+/// each batch is staged under `.staging` first, a partition's staged parts are compacted into
+/// a single file that is added to the manifest once every block of the partition was written,
+/// or on `finish`
+pub struct ParquetSink {
+    inner: Arc<ParquetWriter>,
+}
+
+/// stages and compacts the files on the blocking pool, as encoding and writing them would hold
+/// up the other sinks' writes
+struct ParquetWriter {
+    args: ParquetArgs,
+    state: Mutex<ParquetState>,
+    /// the block after the writer's completed block, every partition ending before it is
+    /// complete
+    completed_end: AtomicU64,
+}
+
+struct ParquetState {
+    manifest: ParquetManifest,
+    /// the staged parts of each open partition, relative to `--parquet-dir`
+    staged: HashMap<PartitionKey, Vec<PathBuf>>,
+}
+
+impl ParquetSink {
+    /// picks up the manifest and the staged parts of a previous run in the same directory
+    pub fn new(args: ParquetArgs) -> eyre::Result<Self> {
+        std::fs::create_dir_all(&args.parquet_dir)?;
+        let mut manifest = ParquetManifest::load(&args.parquet_dir.join("manifest.json"))?;
+        info!(target: "uniV3::db", "writing parquet files to {}", args.parquet_dir.display());
+
+        let mut staged: HashMap<PartitionKey, Vec<PathBuf>> = HashMap::new();
+        for (key, path, part) in
+            staged_parts(&args.parquet_dir, args.parquet_blocks_per_file.max(1))?
+        {
+            manifest.next_part = manifest.next_part.max(part + 1);
+            staged.entry(key).or_default().push(path);
+        }
+        if !staged.is_empty() {
+            info!(target: "uniV3::db", "picked up the staged parts of {} parquet partitions", staged.len());
+        }
+
+        Ok(Self {
+            inner: Arc::new(ParquetWriter {
+                args,
+                state: Mutex::new(ParquetState { manifest, staged }),
+                completed_end: AtomicU64::new(0),
+            }),
+        })
+    }
+}
+
+impl ParquetWriter {
+    fn manifest_path(&self) -> PathBuf {
+        self.args.parquet_dir.join("manifest.json")
+    }
+
+    fn blocks_per_file(&self) -> u64 {
+        self.args.parquet_blocks_per_file.max(1)
+    }
+
+    /// `<table>/pool=<address>/blocks=<start>-<end>`
+    fn partition_dir(&self, (table, pool_address, partition): PartitionKey) -> PathBuf {
+        let start = partition * self.blocks_per_file();
+        PathBuf::from(table.as_str())
+            .join(format!(
+                "pool={}",
+                format!("{:?}", pool_address).to_lowercase()
+            ))
+            .join(format!(
+                "blocks={}-{}",
+                start,
+                start + self.blocks_per_file() - 1
+            ))
+    }
+
+    /// stages the rows of each pool and block range as a new part
+    fn stage_rows<R: ParquetRow>(
+        &self,
+        state: &mut ParquetState,
+        rows: &[R],
+        staged: &mut Vec<(PartitionKey, PathBuf)>,
+    ) -> eyre::Result<()> {
+        let mut partitions: HashMap<PartitionKey, Vec<&R>> = HashMap::new();
+        for row in rows {
+            partitions
+                .entry((
+                    R::TABLE,
+                    row.pool_address(),
+                    row.block_number() / self.blocks_per_file(),
+                ))
+                .or_default()
+                .push(row);
+        }
+
+        for (key, rows) in partitions {
+            let path = PathBuf::from(STAGING_DIR)
+                .join(self.partition_dir(key))
+                .join(format!("part-{:08}.parquet", state.manifest.next_part));
+            state.manifest.next_part += 1;
+
+            let batch = R::record_batch(&rows, self.args.parquet_big_ints)?;
+            write_file(&self.args.parquet_dir.join(&path), &batch)?;
+            staged.push((key, path));
+        }
+
+        Ok(())
+    }
+
+    fn stage_batch(
+        &self,
+        state: &mut ParquetState,
+        values: Vec<PoolData>,
+        staged: &mut Vec<(PartitionKey, PathBuf)>,
+    ) -> eyre::Result<()> {
+        let (tick_info, slot0, trades, candles) = PoolData::combine_many(values);
+
+        self.stage_rows(state, &tick_info, staged)?;
+        self.stage_rows(state, &slot0, staged)?;
+        self.stage_rows(state, &trades, staged)?;
+        self.stage_rows(state, &candles, staged)
+    }
+
+    /// compacts the staged parts of the partition into one file and adds it to the manifest
+    fn close_partition(&self, state: &mut ParquetState, key: PartitionKey) -> eyre::Result<()> {
+        let (table, pool_address, _) = key;
+        let parts = state.staged.get(&key).cloned().unwrap_or_default();
+
+        let mut batches = Vec::new();
+        for part in &parts {
+            batches.extend(read_batches(&self.args.parquet_dir.join(part))?);
+        }
+        let (mut start_block, mut end_block) = (u64::MAX, 0);
+        for batch in &batches {
+            for block in block_column(batch, table)?.values() {
+                start_block = start_block.min(*block);
+                end_block = end_block.max(*block);
+            }
+        }
+        let rows = batches.iter().map(RecordBatch::num_rows).sum::<usize>();
+
+        if let Some(first) = batches.first().filter(|_| rows > 0) {
+            let mut manifest = state.manifest.clone();
+            let path = self
+                .partition_dir(key)
+                .join(format!("part-{:08}.parquet", manifest.next_part));
+            manifest.next_part += 1;
+
+            let batch = arrow::compute::concat_batches(&first.schema(), &batches)?;
+            write_file(&self.args.parquet_dir.join(&path), &batch)?;
+            manifest.files.push(ParquetFile {
+                path,
+                table,
+                pool_address,
+                start_block,
+                end_block,
+                rows,
+            });
+            manifest.save(&self.manifest_path())?;
+            state.manifest = manifest;
+        }
+
+        state.staged.remove(&key);
+        for part in parts {
+            let _ = std::fs::remove_file(self.args.parquet_dir.join(part));
+        }
+
+        Ok(())
+    }
+
+    /// closes the partitions whose blocks were all written, or all of them
+    fn close_partitions(&self, state: &mut ParquetState, complete_only: bool) -> eyre::Result<()> {
+        let blocks_per_file = self.blocks_per_file();
+        let completed_end = self.completed_end.load(Ordering::Acquire);
+        let closed = state
+            .staged
+            .keys()
+            .filter(|(_, _, partition)| {
+                !complete_only || (partition + 1) * blocks_per_file <= completed_end
+            })
+            .copied()
+            .collect::<Vec<_>>();
+
+        for key in closed {
+            self.close_partition(state, key)?;
+        }
+
+        Ok(())
+    }
+
+    fn insert(&self, values: Vec<PoolData>) -> eyre::Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        // the batch is retried whole, so the parts it already staged would be duplicated
+        let mut staged = Vec::new();
+        if let Err(e) = self.stage_batch(&mut state, values, &mut staged) {
+            for (_, path) in &staged {
+                let _ = std::fs::remove_file(self.args.parquet_dir.join(path));
+            }
+            return Err(e);
+        }
+        debug!(target: "uniV3::db", "staged {} parquet parts", staged.len());
+        for (key, path) in staged {
+            state.staged.entry(key).or_default().push(path);
+        }
+
+        // the batch is already staged, a partition that fails to close is closed later
+        if let Err(e) = self.close_partitions(&mut state, true) {
+            warn!(target: "uniV3::db", "failed to close parquet partitions, retrying with the next batch - {:?}", e);
+        }
+
+        Ok(())
+    }
+
+    /// deletes the files and staged parts that only hold retracted blocks and rewrites the ones
+    /// that straddle `from_block` without them
+    fn retract(&self, from_block: u64, pools: &[Address]) -> eyre::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+
+        let blocks_per_file = self.blocks_per_file();
+        for ((table, pool_address, partition), parts) in state.staged.iter_mut() {
+            if !pools.contains(pool_address) || (*partition + 1) * blocks_per_file <= from_block {
+                continue;
+            }
+
+            let mut emptied = Vec::new();
+            for part in parts.iter() {
+                let path = self.args.parquet_dir.join(part);
+                if retract_file(&path, *table, from_block)?.1 == 0 {
+                    std::fs::remove_file(path)?;
+                    emptied.push(part.clone());
+                }
+            }
+            parts.retain(|part| !emptied.contains(part));
+        }
+        state.staged.retain(|_, parts| !parts.is_empty());
+        self.completed_end.fetch_min(from_block, Ordering::AcqRel);
+
+        let mut files = Vec::new();
+        for mut file in std::mem::take(&mut state.manifest.files) {
+            let path = self.args.parquet_dir.join(&file.path);
+            if file.end_block < from_block || !pools.contains(&file.pool_address) {
+                files.push(file);
+            } else if file.start_block >= from_block {
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+            } else {
+                let (end_block, rows) = retract_file(&path, file.table, from_block)?;
+                file.end_block = end_block;
+                file.rows = rows;
+                files.push(file);
+            }
+        }
+        state.manifest.files = files;
+        state.manifest.save(&self.manifest_path())
+    }
+
+    /// closes every partition that is still open
+    fn finish(&self) -> eyre::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.close_partitions(&mut state, false)
+    }
+}
+
+#[async_trait]
+impl PoolDataSink for ParquetSink {
+    fn name(&self) -> &'static str {
+        "parquet"
+    }
+
+    async fn insert(&self, values: &[PoolData]) -> eyre::Result<()> {
+        let (inner, values) = (self.inner.clone(), values.to_vec());
+        tokio::task::spawn_blocking(move || inner.insert(values)).await?
+    }
+
+    async fn retract(&self, from_block: u64, pools: &[Address]) -> eyre::Result<()> {
+        let (inner, pools) = (self.inner.clone(), pools.to_vec());
+        tokio::task::spawn_blocking(move || inner.retract(from_block, &pools)).await?
+    }
+
+    async fn finish(&self) -> eyre::Result<()> {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || inner.finish()).await?
+    }
+
+    /// the partitions it completes are closed with the next batch
+    fn on_completed(&self, block: u64) {
+        self.inner.completed_end.store(block + 1, Ordering::Release);
+    }
+}
+
+/// the parts a previous run staged, with their partition and part number
+fn staged_parts(
+    dir: &Path,
+    blocks_per_file: u64,
+) -> eyre::Result<Vec<(PartitionKey, PathBuf, u64)>> {
+    let name = |path: &Path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    let mut parts = Vec::new();
+    for table in [
+        ParquetTable::TickInfo,
+        ParquetTable::Slot0,
+        ParquetTable::Trades,
+        ParquetTable::Candles,
+    ] {
+        let table_dir = dir.join(STAGING_DIR).join(table.as_str());
+        if !table_dir.exists() {
+            continue;
+        }
+
+        for pool_dir in std::fs::read_dir(table_dir)? {
+            let pool_dir = pool_dir?.path();
+            let Some(pool_address) = name(&pool_dir)
+                .strip_prefix("pool=")
+                .and_then(|pool| pool.parse::<Address>().ok())
+            else {
+                continue;
+            };
+
+            for blocks_dir in std::fs::read_dir(&pool_dir)? {
+                let blocks_dir = blocks_dir?.path();
+                let Some(start) = name(&blocks_dir)
+                    .strip_prefix("blocks=")
+                    .and_then(|blocks| blocks.split('-').next()?.parse::<u64>().ok())
+                else {
+                    continue;
+                };
+
+                for part in std::fs::read_dir(&blocks_dir)? {
+                    let part = part?.path();
+                    // a part left half written is still a `.tmp` file
+                    let Some(number) = name(&part)
+                        .strip_prefix("part-")
+                        .and_then(|part| part.strip_suffix(".parquet")?.parse::<u64>().ok())
+                    else {
+                        continue;
+                    };
+                    parts.push((
+                        (table, pool_address, start / blocks_per_file),
+                        part.strip_prefix(dir)?.to_path_buf(),
+                        number,
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(parts)
+}
+
+fn writer_properties() -> WriterProperties {
+    WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build()
+}
+
+fn write_file(path: &Path, batch: &RecordBatch) -> eyre::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let tmp_path = path.with_extension("parquet.tmp");
+    let mut writer = ArrowWriter::try_new(
+        File::create(&tmp_path)?,
+        batch.schema(),
+        Some(writer_properties()),
+    )?;
+    writer.write(batch)?;
+    writer.close()?;
+    std::fs::rename(tmp_path, path)?;
+
+    Ok(())
+}
+
+fn read_batches(path: &Path) -> eyre::Result<Vec<RecordBatch>> {
+    Ok(ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?
+        .build()?
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// the column the table is partitioned and retracted by
+fn block_column(batch: &RecordBatch, table: ParquetTable) -> eyre::Result<&UInt64Array> {
+    batch
+        .column_by_name(table.block_column())
+        .and_then(|column| column.as_any().downcast_ref::<UInt64Array>())
+        .ok_or_else(|| eyre::ErrReport::msg(format!("no {} column", table.block_column())))
+}
+
+/// rewrites the file without the rows from `from_block`, returning its new last block and rows
+fn retract_file(path: &Path, table: ParquetTable, from_block: u64) -> eyre::Result<(u64, usize)> {
+    let mut batches = Vec::new();
+    let (mut end_block, mut rows) = (0, 0);
+    for batch in read_batches(path)? {
+        let keep = BooleanArray::from_iter(
+            block_column(&batch, table)?
+                .values()
+                .iter()
+                .map(|block| Some(*block < from_block)),
+        );
+        let batch = filter_record_batch(&batch, &keep)?;
+
+        end_block = block_column(&batch, table)?
+            .values()
+            .iter()
+            .copied()
+            .fold(end_block, u64::max);
+        rows += batch.num_rows();
+        batches.push(batch);
+    }
+
+    let Some(first) = batches.first() else {
+        return Ok((end_block, rows));
+    };
+    let batch = arrow::compute::concat_batches(&first.schema(), &batches)?;
+    write_file(path, &batch)?;
+
+    Ok((end_block, rows))
+}
+
+/// a value written to its own table, with the columns of its `Row`
+trait ParquetRow: Row {
+    const TABLE: ParquetTable;
+
+    fn pool_address(&self) -> Address;
+
+    /// the block the value is partitioned and retracted by
+    fn block_number(&self) -> u64;
+
+    fn record_batch(rows: &[&Self], encoding: BigIntEncoding) -> eyre::Result<RecordBatch>;
+}
+
+impl ParquetRow for PoolTickInfo {
+    const TABLE: ParquetTable = ParquetTable::TickInfo;
+
+    fn pool_address(&self) -> Address {
+        self.pool_address
+    }
+
+    fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    fn record_batch(rows: &[&Self], encoding: BigIntEncoding) -> eyre::Result<RecordBatch> {
+        Columns::new(Self::COLUMN_NAMES, encoding)
+            .u64(rows.iter().map(|r| r.block_number))
+            .hex(rows.iter().map(|r| r.pool_address))
+            .hex(rows.iter().map(|r| r.tx_hash))
+            .u64(rows.iter().map(|r| r.tx_index))
+            .i32(rows.iter().map(|r| r.tick))
+            .i32(rows.iter().map(|r| r.tick_spacing))
+            .big_int(rows.iter().map(|r| r.liquidity_gross))?
+            .big_int(rows.iter().map(|r| r.liquidity_net))?
+            .big_int(rows.iter().map(|r| r.fee_growth_outside_0_x128))?
+            .big_int(rows.iter().map(|r| r.fee_growth_outside_1_x128))?
+            .i64(rows.iter().map(|r| r.tick_cumulative_outside))
+            .big_int(rows.iter().map(|r| r.seconds_per_liquidity_outside_x128))?
+            .u32(rows.iter().map(|r| r.seconds_outside))
+            .bool(rows.iter().map(|r| r.initialized))
+            .finish()
+    }
+}
+
+impl ParquetRow for PoolSlot0 {
+    const TABLE: ParquetTable = ParquetTable::Slot0;
+
+    fn pool_address(&self) -> Address {
+        self.pool_address
+    }
+
+    fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    fn record_batch(rows: &[&Self], encoding: BigIntEncoding) -> eyre::Result<RecordBatch> {
+        Columns::new(Self::COLUMN_NAMES, encoding)
+            .u64(rows.iter().map(|r| r.block_number))
+            .hex(rows.iter().map(|r| r.pool_address))
+            .hex(rows.iter().map(|r| r.token0))
+            .u8(rows.iter().map(|r| r.token0_decimals))
+            .hex(rows.iter().map(|r| r.token1))
+            .u8(rows.iter().map(|r| r.token1_decimals))
+            .hex(rows.iter().map(|r| r.tx_hash))
+            .u64(rows.iter().map(|r| r.tx_index))
+            .i32(rows.iter().map(|r| r.tick))
+            .big_int(rows.iter().map(|r| r.sqrt_price_x96))?
+            .f64(rows.iter().map(|r| r.calculated_price))
+            .u16(rows.iter().map(|r| r.observation_index))
+            .u16(rows.iter().map(|r| r.observation_cardinality))
+            .u16(rows.iter().map(|r| r.observation_cardinality_next))
+            .u8(rows.iter().map(|r| r.fee_protocol))
+            .bool(rows.iter().map(|r| r.unlocked))
+            .finish()
+    }
+}
+
+impl ParquetRow for PoolTrade {
+    const TABLE: ParquetTable = ParquetTable::Trades;
+
+    fn pool_address(&self) -> Address {
+        self.pool_address
+    }
+
+    fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    fn record_batch(rows: &[&Self], encoding: BigIntEncoding) -> eyre::Result<RecordBatch> {
+        Columns::new(Self::COLUMN_NAMES, encoding)
+            .u64(rows.iter().map(|r| r.block_number))
+            .hex(rows.iter().map(|r| r.tx_hash))
+            .hex(rows.iter().map(|r| r.pool_address))
+            .hex(rows.iter().map(|r| r.token_in))
+            .u8(rows.iter().map(|r| r.token_in_decimals))
+            .big_int(rows.iter().map(|r| r.token_in_amount))?
+            .hex(rows.iter().map(|r| r.token_out))
+            .u8(rows.iter().map(|r| r.token_out_decimals))
+            .big_int(rows.iter().map(|r| r.token_out_amount))?
+            .f64(rows.iter().map(|r| r.calculated_price))
+            .finish()
+    }
+}
+
+impl ParquetRow for PoolCandle {
+    const TABLE: ParquetTable = ParquetTable::Candles;
+
+    fn pool_address(&self) -> Address {
+        self.pool_address
+    }
+
+    fn block_number(&self) -> u64 {
+        self.end_block
+    }
+
+    fn record_batch(rows: &[&Self], encoding: BigIntEncoding) -> eyre::Result<RecordBatch> {
+        Columns::new(Self::COLUMN_NAMES, encoding)
+            .hex(rows.iter().map(|r| r.pool_address))
+            .string(rows.iter().map(|r| r.interval.clone()))
+            .u64(rows.iter().map(|r| r.bucket_start))
+            .u64(rows.iter().map(|r| r.start_block))
+            .u64(rows.iter().map(|r| r.end_block))
+            .hex(rows.iter().map(|r| r.token0))
+            .hex(rows.iter().map(|r| r.token1))
+            .f64(rows.iter().map(|r| r.open))
+            .f64(rows.iter().map(|r| r.high))
+            .f64(rows.iter().map(|r| r.low))
+            .f64(rows.iter().map(|r| r.close))
+            .f64(rows.iter().map(|r| r.volume_token0))
+            .f64(rows.iter().map(|r| r.volume_token1))
+            .u64(rows.iter().map(|r| r.trade_count))
+            .finish()
+    }
+}
+
+/// an integer wider than the native parquet types
+trait BigInt: Display {
+    const BYTES: i32;
+
+    fn big_endian_bytes(&self) -> Vec<u8>;
+}
+
+impl BigInt for U256 {
+    const BYTES: i32 = 32;
+
+    fn big_endian_bytes(&self) -> Vec<u8> {
+        self.to_be_bytes_vec()
+    }
+}
+
+impl BigInt for I256 {
+    const BYTES: i32 = 32;
+
+    fn big_endian_bytes(&self) -> Vec<u8> {
+        self.into_raw().to_be_bytes_vec()
+    }
+}
+
+impl BigInt for u128 {
+    const BYTES: i32 = 16;
+
+    fn big_endian_bytes(&self) -> Vec<u8> {
+        self.to_be_bytes().to_vec()
+    }
+}
+
+impl BigInt for i128 {
+    const BYTES: i32 = 16;
+
+    fn big_endian_bytes(&self) -> Vec<u8> {
+        self.to_be_bytes().to_vec()
+    }
+}
+
+/// builds a record batch one column at a time, in the order of the row's `COLUMN_NAMES`
+struct Columns {
+    names: &'static [&'static str],
+    encoding: BigIntEncoding,
+    arrays: Vec<ArrayRef>,
+}
+
+impl Columns {
+    fn new(names: &'static [&'static str], encoding: BigIntEncoding) -> Self {
+        Self {
+            names,
+            encoding,
+            arrays: Vec::new(),
+        }
+    }
+
+    fn column(mut self, array: ArrayRef) -> Self {
+        self.arrays.push(array);
+        self
+    }
+
+    fn u64(self, values: impl Iterator<Item = u64>) -> Self {
+        self.column(Arc::new(UInt64Array::from_iter_values(values)))
+    }
+
+    fn u32(self, values: impl Iterator<Item = u32>) -> Self {
+        self.column(Arc::new(UInt32Array::from_iter_values(values)))
+    }
+
+    fn u16(self, values: impl Iterator<Item = u16>) -> Self {
+        self.column(Arc::new(UInt16Array::from_iter_values(values)))
+    }
+
+    fn u8(self, values: impl Iterator<Item = u8>) -> Self {
+        self.column(Arc::new(UInt8Array::from_iter_values(values)))
+    }
+
+    fn i64(self, values: impl Iterator<Item = i64>) -> Self {
+        self.column(Arc::new(Int64Array::from_iter_values(values)))
+    }
+
+    fn i32(self, values: impl Iterator<Item = i32>) -> Self {
+        self.column(Arc::new(Int32Array::from_iter_values(values)))
+    }
+
+    fn f64(self, values: impl Iterator<Item = f64>) -> Self {
+        self.column(Arc::new(Float64Array::from_iter_values(values)))
+    }
+
+    fn bool(self, values: impl Iterator<Item = bool>) -> Self {
+        self.column(Arc::new(BooleanArray::from_iter(values.map(Some))))
+    }
+
+    fn string(self, values: impl Iterator<Item = String>) -> Self {
+        self.column(Arc::new(StringArray::from_iter_values(values)))
+    }
+
+    fn hex<T: Debug>(self, values: impl Iterator<Item = T>) -> Self {
+        self.string(values.map(hex_string))
+    }
+
+    fn big_int<T: BigInt>(self, values: impl Iterator<Item = T>) -> eyre::Result<Self> {
+        let array: ArrayRef = match self.encoding {
+            BigIntEncoding::FixedBinary => {
+                let mut builder = FixedSizeBinaryBuilder::new(T::BYTES);
+                for value in values {
+                    builder.append_value(value.big_endian_bytes())?;
+                }
+                Arc::new(builder.finish())
+            }
+            BigIntEncoding::Decimal => {
+                Arc::new(StringArray::from_iter_values(values.map(|v| v.to_string())))
+            }
+        };
+
+        Ok(self.column(array))
+    }
+
+    fn finish(self) -> eyre::Result<RecordBatch> {
+        if self.arrays.len() != self.names.len() {
+            return Err(eyre::ErrReport::msg(format!(
+                "built {} columns for the {} columns {:?}",
+                self.arrays.len(),
+                self.names.len(),
+                self.names
+            )));
+        }
+
+        let fields = self
+            .names
+            .iter()
+            .zip(&self.arrays)
+            .map(|(name, array)| Field::new(*name, array.data_type().clone(), false))
+            .collect::<Vec<_>>();
+
+        Ok(RecordBatch::try_new(
+            Arc::new(Schema::new(fields)),
+            self.arrays,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pools::types::trade, utils::temp_path};
+
+    #[tokio::test]
+    async fn test_parquet_partitions_and_retracts() {
+        let dir = temp_path("parquet");
+
+        let sink = ParquetSink::new(ParquetArgs {
+            parquet_dir: dir.clone(),
+            parquet_blocks_per_file: 100,
+            parquet_big_ints: BigIntEncoding::Decimal,
+        })
+        .unwrap();
+
+        sink.insert(&[trade(150, 5), trade(210, 9)]).await.unwrap();
+        assert!(!dir.join("manifest.json").exists());
+
+        // a block of the first partition can still arrive until the writer completes it
+        sink.on_completed(150);
+        sink.insert(&[trade(180, 7)]).await.unwrap();
+        assert!(!dir.join("manifest.json").exists());
+
+        // the first partition is closed with the next batch, the second is only staged
+        sink.on_completed(210);
+        sink.insert(&[]).await.unwrap();
+        let manifest = ParquetManifest::load(&dir.join("manifest.json")).unwrap();
+        let ranges = manifest
+            .files
+            .iter()
+            .map(|file| (file.start_block, file.end_block, file.rows))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![(150, 180, 2)]);
+        assert_eq!(sink.inner.state.lock().unwrap().staged.len(), 1);
+
+        // the files of other pools are left alone
+        sink.retract(160, &[Address::with_last_byte(9)])
+            .await
+            .unwrap();
+        assert_eq!(
+            ParquetManifest::load(&dir.join("manifest.json")).unwrap(),
+            manifest
+        );
+
+        sink.retract(160, &[Address::with_last_byte(1)])
+            .await
+            .unwrap();
+        assert!(sink.inner.state.lock().unwrap().staged.is_empty());
+
+        let manifest = ParquetManifest::load(&dir.join("manifest.json")).unwrap();
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!(
+            (manifest.files[0].start_block, manifest.files[0].end_block),
+            (150, 150)
+        );
+
+        let path = dir.join(&manifest.files[0].path);
+        let batch = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let amounts = batch
+            .column_by_name("token_out_amount")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(amounts.value(0), "-5");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_parquet_compacts_each_partition() {
+        let dir = temp_path("parquet");
+        let args = ParquetArgs {
+            parquet_dir: dir.clone(),
+            parquet_blocks_per_file: 100,
+            parquet_big_ints: BigIntEncoding::FixedBinary,
+        };
+
+        let sink = ParquetSink::new(args.clone()).unwrap();
+        sink.insert(&[trade(110, 1)]).await.unwrap();
+        sink.insert(&[trade(120, 2), trade(130, 3)]).await.unwrap();
+        assert!(!dir.join("manifest.json").exists());
+
+        // a new sink picks up the staged parts and closes them on finish
+        drop(sink);
+        let sink = ParquetSink::new(args).unwrap();
+        sink.insert(&[trade(150, 4)]).await.unwrap();
+        sink.finish().await.unwrap();
+
+        let manifest = ParquetManifest::load(&dir.join("manifest.json")).unwrap();
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!(
+            (
+                manifest.files[0].start_block,
+                manifest.files[0].end_block,
+                manifest.files[0].rows
+            ),
+            (110, 150, 4)
+        );
+        assert_eq!(
+            manifest.files[0].path.parent().unwrap(),
+            Path::new("trades/pool=0x0000000000000000000000000000000000000001/blocks=100-199")
+        );
+        assert!(staged_parts(&dir, 100).unwrap().is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
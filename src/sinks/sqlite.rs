@@ -0,0 +1,273 @@
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use alloy_primitives::Address;
+use async_trait::async_trait;
+use clap::Args;
+use itertools::Itertools;
+use rusqlite::{params, params_from_iter, types::Value, Connection, Transaction};
+use tracing::info;
+
+use super::{
+    text::{hex, TextRow},
+    PoolDataSink,
+};
+use crate::pools::types::{PoolCandle, PoolData, PoolSlot0, PoolTickInfo, PoolTrade};
+
+/// the tables of `src/sql/tables`, with the 128 and 256 bit integers as decimal text
+const SCHEMA: &str = include_str!("../sql/sqlite/schema.sql");
+
+/// where the sqlite sink writes to
+#[derive(Debug, Clone, PartialEq, Args)]
+#[command(next_help_heading = "SQLite sink")]
+pub struct SqliteArgs {
+    /// database file the `sqlite` sink writes to, created with its tables if it doesn't exist
+    #[arg(long, default_value = "uniV3.sqlite")]
+    pub sqlite_path: PathBuf,
+}
+
+/// writes the values to the `uni_v3_*` tables of a local sqlite database
+///
+/// a row replaces the one with the same sorting key, like the `ReplacingMergeTree` tables keep
+/// the last inserted row of each key, so re-inserting a batch is idempotent
+pub struct SqliteSink {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteSink {
+    pub fn new(args: SqliteArgs) -> eyre::Result<Self> {
+        let conn = Connection::open(&args.sqlite_path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        info!(target: "uniV3::db", "writing to sqlite db {}", args.sqlite_path.display());
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn upsert_rows<R: SqliteRow>(
+        tx: &Transaction,
+        rows: &[R],
+        last_updated: i64,
+    ) -> eyre::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut stmt =
+            tx.prepare_cached(&upsert_query(&sqlite_table::<R>(), R::COLUMN_NAMES, R::KEY))?;
+        for row in rows {
+            let values = row
+                .values()
+                .into_iter()
+                .map(sqlite_value)
+                .chain([Value::Integer(last_updated)]);
+            stmt.execute(params_from_iter(values))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PoolDataSink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn insert(&self, values: &[PoolData]) -> eyre::Result<()> {
+        let (tick_info, slot0, trades, candles) = PoolData::combine_many(values.to_vec());
+        let last_updated = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        Self::upsert_rows(&tx, &tick_info, last_updated)?;
+        Self::upsert_rows(&tx, &slot0, last_updated)?;
+        Self::upsert_rows(&tx, &trades, last_updated)?;
+        Self::upsert_rows(&tx, &candles, last_updated)?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    async fn retract(&self, from_block: u64, pools: &[Address]) -> eyre::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let pools = pools
+            .iter()
+            .map(|pool| sqlite_value(hex(*pool)))
+            .collect::<Vec<_>>();
+        for (table, block_column) in [
+            (sqlite_table::<PoolTickInfo>(), PoolTickInfo::BLOCK_COLUMN),
+            (sqlite_table::<PoolSlot0>(), PoolSlot0::BLOCK_COLUMN),
+            (sqlite_table::<PoolTrade>(), PoolTrade::BLOCK_COLUMN),
+            (sqlite_table::<PoolCandle>(), PoolCandle::BLOCK_COLUMN),
+        ] {
+            let mut delete = tx.prepare_cached(&format!(
+                "DELETE FROM {table} WHERE `{block_column}` >= ?1 AND `pool_address` = ?2"
+            ))?;
+            for pool in &pools {
+                delete.execute(params![from_block as i64, pool])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+/// inserts a row, replacing the row with the same key if it's not newer
+fn upsert_query(table: &str, columns: &[&str], key: &[&str]) -> String {
+    let columns = columns
+        .iter()
+        .copied()
+        .chain(["last_updated"])
+        .collect::<Vec<_>>();
+
+    format!(
+        "INSERT INTO {table} ({}) VALUES ({}) ON CONFLICT ({}) DO UPDATE SET {} WHERE excluded.`last_updated` >= {table}.`last_updated`",
+        columns.iter().map(|col| format!("`{col}`")).join(", "),
+        (1..=columns.len()).map(|i| format!("?{i}")).join(", "),
+        key.iter().map(|col| format!("`{col}`")).join(", "),
+        columns
+            .iter()
+            .filter(|col| !key.contains(*col))
+            .map(|col| format!("`{col}` = excluded.`{col}`"))
+            .join(", "),
+    )
+}
+
+/// a value's row in its `uni_v3_*` table, with the columns and values of its `TextRow`
+trait SqliteRow: TextRow {
+    /// the `ORDER BY` key of the clickhouse table
+    const KEY: &'static [&'static str];
+    /// the column reorgs are retracted by
+    const BLOCK_COLUMN: &'static str = "block_number";
+}
+
+fn sqlite_table<R: TextRow>() -> String {
+    format!("uni_v3_{}", R::TABLE)
+}
+
+/// the text sinks' value as sqlite stores it, the u64 columns never get near the signed 64 bit
+/// limit of sqlite integers
+fn sqlite_value(value: serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(b as i64),
+        serde_json::Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => Value::Integer(i),
+            (None, Some(u)) => Value::Integer(u as i64),
+            _ => Value::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => Value::Text(s),
+        value => Value::Text(value.to_string()),
+    }
+}
+
+impl SqliteRow for PoolTickInfo {
+    const KEY: &'static [&'static str] = &["block_number", "pool_address", "tx_hash", "tick"];
+}
+
+impl SqliteRow for PoolSlot0 {
+    const KEY: &'static [&'static str] = &["block_number", "pool_address", "tx_hash", "tick"];
+}
+
+impl SqliteRow for PoolTrade {
+    const KEY: &'static [&'static str] = &["block_number", "pool_address", "tx_hash"];
+}
+
+impl SqliteRow for PoolCandle {
+    const KEY: &'static [&'static str] = &["pool_address", "interval", "bucket_start"];
+    /// a candle that started before a reorg is emitted again and replaces its row
+    const BLOCK_COLUMN: &'static str = "start_block";
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::Address;
+    use clickhouse::Row;
+
+    use super::*;
+    use crate::{pools::types::trade, utils::temp_path};
+
+    fn priced_trade(block_number: u64, amount: i64, calculated_price: f64) -> PoolData {
+        let PoolData::Trade(trade) = trade(block_number, amount) else {
+            unreachable!()
+        };
+        PoolData::Trade(PoolTrade {
+            calculated_price,
+            ..trade
+        })
+    }
+
+    fn trades(sink: &SqliteSink) -> Vec<(i64, String, f64)> {
+        let conn = sink.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT block_number, token_out_amount, calculated_price FROM uni_v3_trades ORDER BY block_number")
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_upserts_and_retracts() {
+        let path = temp_path("sink.sqlite");
+
+        let sink = SqliteSink::new(SqliteArgs {
+            sqlite_path: path.clone(),
+        })
+        .unwrap();
+        sink.insert(&[trade(10, 5), trade(11, 7)]).await.unwrap();
+        // the same key replaces the row
+        sink.insert(&[priced_trade(11, 7, 2.0)]).await.unwrap();
+        assert_eq!(
+            trades(&sink),
+            vec![(10, "-5".to_string(), 1.0), (11, "-7".to_string(), 2.0)]
+        );
+
+        // the rows of other pools are left alone
+        sink.retract(10, &[Address::with_last_byte(9)])
+            .await
+            .unwrap();
+        assert_eq!(trades(&sink).len(), 2);
+
+        sink.retract(11, &[Address::with_last_byte(1)])
+            .await
+            .unwrap();
+        assert_eq!(trades(&sink), vec![(10, "-5".to_string(), 1.0)]);
+
+        drop(sink);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn columns<R: TextRow>(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(&format!("PRAGMA table_info({})", sqlite_table::<R>()))
+            .unwrap();
+        stmt.query_map([], |row| row.get::<_, String>(1))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+            .into_iter()
+            .filter(|col| col != "last_updated")
+            .collect()
+    }
+
+    #[test]
+    fn test_schema_matches_rows() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+
+        assert_eq!(columns::<PoolTickInfo>(&conn), PoolTickInfo::COLUMN_NAMES);
+        assert_eq!(columns::<PoolSlot0>(&conn), PoolSlot0::COLUMN_NAMES);
+        assert_eq!(columns::<PoolTrade>(&conn), PoolTrade::COLUMN_NAMES);
+        assert_eq!(columns::<PoolCandle>(&conn), PoolCandle::COLUMN_NAMES);
+    }
+}
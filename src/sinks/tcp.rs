@@ -0,0 +1,308 @@
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
+
+use alloy_primitives::Address;
+use async_trait::async_trait;
+use clap::Args;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{self, error::RecvError},
+};
+use tracing::{debug, info, warn};
+
+use super::{
+    text::{retract_fields, text_fields, JsonLine},
+    PoolDataSink,
+};
+use crate::pools::types::PoolData;
+
+/// the `type` of each data type's messages
+const DATA_TYPES: [&str; 4] = ["tick_info", "slot0", "trades", "candles"];
+
+/// where the tcp sink listens for subscribers
+#[derive(Debug, Clone, PartialEq, Args)]
+#[command(next_help_heading = "TCP sink")]
+pub struct TcpArgs {
+    /// address the `tcp` sink accepts subscribers on
+    #[arg(long, default_value = "127.0.0.1:9100")]
+    pub tcp_addr: SocketAddr,
+
+    /// messages buffered for each subscriber before the oldest are skipped
+    #[arg(long, default_value = "65536")]
+    pub tcp_buffer: usize,
+}
+
+/// what a subscriber wants to receive, every pool or data type if empty
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Subscription {
+    #[serde(default)]
+    pub pools: HashSet<Address>,
+    #[serde(default)]
+    pub types: HashSet<String>,
+}
+
+impl Subscription {
+    fn matches(&self, message: &StreamMessage) -> bool {
+        match &message.kind {
+            MessageKind::Value {
+                table,
+                pool_address,
+            } => {
+                (self.pools.is_empty() || self.pools.contains(pool_address))
+                    && (self.types.is_empty() || self.types.contains(*table))
+            }
+            // a retraction takes back values of every type
+            MessageKind::Retract { pools } => {
+                self.pools.is_empty() || pools.iter().any(|pool| self.pools.contains(pool))
+            }
+        }
+    }
+}
+
+/// what a message sent to the subscribers is about
+#[derive(Debug, Clone)]
+enum MessageKind {
+    Value {
+        table: &'static str,
+        pool_address: Address,
+    },
+    Retract {
+        pools: Vec<Address>,
+    },
+}
+
+/// a json line sent to the subscribers
+#[derive(Debug, Clone)]
+struct StreamMessage {
+    kind: MessageKind,
+    line: String,
+}
+
+/// broadcasts every value as a json line to the subscribers connected over tcp, in the format
+/// of the `jsonl` sink, as soon as the writer receives its block rather than when it's inserted
+///
+/// a subscriber first sends a line with its `Subscription`, answered with `{"type":"subscribed"}`,
+/// and then gets the matching values and the `{"type":"retract","from_block":<block>,"pools":[..]}`
+/// of the reorgs of its pools
+pub struct TcpSink {
+    tx: broadcast::Sender<Arc<StreamMessage>>,
+    local_addr: SocketAddr,
+}
+
+impl TcpSink {
+    /// binds the listener and accepts subscribers in the background
+    pub async fn bind(args: TcpArgs) -> eyre::Result<Self> {
+        let listener = TcpListener::bind(args.tcp_addr).await?;
+        let local_addr = listener.local_addr()?;
+        let (tx, _) = broadcast::channel(args.tcp_buffer.max(1));
+        info!(target: "uniV3::db", "streaming values to subscribers on {local_addr}");
+
+        let sender = tx.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        let sender = sender.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = serve_subscriber(stream, sender).await {
+                                debug!(target: "uniV3::db", "subscriber {peer} disconnected - {:?}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!(target: "uniV3::db", "failed to accept a subscriber - {:?}", e),
+                }
+            }
+        });
+
+        Ok(Self { tx, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// there may be no subscriber, which isn't an error
+    fn broadcast(&self, message: StreamMessage) {
+        let _ = self.tx.send(Arc::new(message));
+    }
+}
+
+#[async_trait]
+impl PoolDataSink for TcpSink {
+    fn name(&self) -> &'static str {
+        "tcp"
+    }
+
+    /// the values were already broadcast by `on_block`
+    async fn insert(&self, _values: &[PoolData]) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    /// the retraction was already broadcast by `on_reorg`
+    async fn retract(&self, _from_block: u64, _pools: &[Address]) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    fn on_block(&self, values: &[PoolData]) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+
+        for value in values {
+            let (table, fields) = text_fields(value);
+            match serde_json::to_string(&JsonLine { table, fields }) {
+                Ok(line) => self.broadcast(StreamMessage {
+                    kind: MessageKind::Value {
+                        table,
+                        pool_address: value.pool_address(),
+                    },
+                    line,
+                }),
+                Err(e) => {
+                    warn!(target: "uniV3::db", "failed to serialize a {table} value - {:?}", e)
+                }
+            }
+        }
+    }
+
+    fn on_reorg(&self, from_block: u64, pools: &[Address]) {
+        let line = JsonLine {
+            table: "retract",
+            fields: retract_fields(from_block, pools),
+        };
+        match serde_json::to_string(&line) {
+            Ok(line) => self.broadcast(StreamMessage {
+                kind: MessageKind::Retract {
+                    pools: pools.to_vec(),
+                },
+                line,
+            }),
+            Err(e) => warn!(target: "uniV3::db", "failed to serialize a retraction - {:?}", e),
+        }
+    }
+}
+
+/// reads the subscription and writes the matching messages until the subscriber disconnects
+async fn serve_subscriber(
+    stream: TcpStream,
+    sender: broadcast::Sender<Arc<StreamMessage>>,
+) -> eyre::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let subscription = match parse_subscription(&line) {
+        Ok(subscription) => subscription,
+        Err(e) => {
+            let error = json!({ "type": "error", "message": e.to_string() });
+            writer.write_all(format!("{error}\n").as_bytes()).await?;
+            return Err(e);
+        }
+    };
+
+    let mut rx = sender.subscribe();
+    writer.write_all(b"{\"type\":\"subscribed\"}\n").await?;
+
+    loop {
+        let line = match rx.recv().await {
+            Ok(message) if subscription.matches(&message) => message.line.clone(),
+            Ok(_) => continue,
+            Err(RecvError::Lagged(skipped)) => {
+                json!({ "type": "lagged", "skipped": skipped }).to_string()
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+
+        writer.write_all(line.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+    }
+}
+
+fn parse_subscription(line: &str) -> eyre::Result<Subscription> {
+    let subscription: Subscription = serde_json::from_str(line.trim())?;
+    if let Some(unknown) = subscription
+        .types
+        .iter()
+        .find(|t| !DATA_TYPES.contains(&t.as_str()))
+    {
+        eyre::bail!("unknown type {unknown}, expected any of {:?}", DATA_TYPES)
+    }
+
+    Ok(subscription)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::pools::types::trade;
+
+    /// the shared trade in the pool `0x..<pool>`
+    fn pool_trade(block_number: u64, pool: u8) -> PoolData {
+        let mut value = trade(block_number, 5);
+        if let PoolData::Trade(trade) = &mut value {
+            trade.pool_address = Address::with_last_byte(pool);
+        }
+        value
+    }
+
+    #[test]
+    fn test_parse_subscription() {
+        assert_eq!(parse_subscription("{}\n").unwrap(), Subscription::default());
+        assert!(parse_subscription(r#"{"types":["swaps"]}"#).is_err());
+
+        let subscription = parse_subscription(
+            r#"{"pools":["0x0000000000000000000000000000000000000001"],"types":["trades"]}"#,
+        )
+        .unwrap();
+        assert!(subscription.pools.contains(&Address::with_last_byte(1)));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_subscriber_gets_matching_values() {
+        let sink = TcpSink::bind(TcpArgs {
+            tcp_addr: "127.0.0.1:0".parse().unwrap(),
+            tcp_buffer: 16,
+        })
+        .await
+        .unwrap();
+
+        let mut stream = TcpStream::connect(sink.local_addr()).await.unwrap();
+        stream
+            .write_all(
+                b"{\"pools\":[\"0x0000000000000000000000000000000000000001\"],\"types\":[\"trades\"]}\n",
+            )
+            .await
+            .unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            r#"{"type":"subscribed"}"#
+        );
+
+        sink.on_block(&[pool_trade(10, 2), pool_trade(11, 1)]);
+        // the reorg of another pool isn't sent
+        sink.on_reorg(10, &[Address::with_last_byte(2)]);
+        sink.on_reorg(11, &[Address::with_last_byte(1)]);
+
+        let trade: Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(trade["type"], "trades");
+        assert_eq!(trade["block_number"], 11);
+
+        let retract: Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(
+            retract,
+            json!({
+                "type": "retract",
+                "from_block": 11,
+                "pools": ["0x0000000000000000000000000000000000000001"]
+            })
+        );
+    }
+}
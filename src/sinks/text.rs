@@ -0,0 +1,473 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use alloy_primitives::Address;
+use async_trait::async_trait;
+use clap::Args;
+use clickhouse::Row;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+use serde_json::Value;
+use tracing::info;
+
+use super::PoolDataSink;
+use crate::pools::types::{PoolCandle, PoolData, PoolSlot0, PoolTickInfo, PoolTrade};
+
+/// the named fields of a value, in column order
+pub(crate) type TextFields = Vec<(&'static str, Value)>;
+
+/// where the jsonl and csv sinks write to
+#[derive(Debug, Clone, PartialEq, Args)]
+#[command(next_help_heading = "Text sinks")]
+pub struct TextArgs {
+    /// file the `jsonl` sink appends to, `-` for stdout
+    #[arg(long, default_value = "-")]
+    pub jsonl_output: PathBuf,
+
+    /// directory the `csv` sink appends a `<table>.csv` per table to
+    #[arg(long, default_value = "csv")]
+    pub csv_dir: PathBuf,
+}
+
+/// writes every value as a json object on its own line, tagged with its table in `type`
+///
+/// a retraction is written as `{"type":"retract","from_block":<block>,"pools":[<address>]}`,
+/// every value of the pools from `from_block` that was written before it should be dropped by
+/// the consumer
+pub struct JsonlSink {
+    out: Mutex<JsonlOutput>,
+}
+
+/// only a file can be truncated back after a failed write
+enum JsonlOutput {
+    Stdout(std::io::Stdout),
+    File(File),
+}
+
+impl JsonlSink {
+    pub fn new(path: &Path) -> eyre::Result<Self> {
+        let out = if path == Path::new("-") {
+            JsonlOutput::Stdout(std::io::stdout())
+        } else {
+            info!(target: "uniV3::db", "writing json lines to {}", path.display());
+            JsonlOutput::File(OpenOptions::new().create(true).append(true).open(path)?)
+        };
+
+        Ok(Self {
+            out: Mutex::new(out),
+        })
+    }
+
+    /// writes the lines at once, a file is truncated back if the write fails part way so the
+    /// retried batch isn't duplicated, but on stdout the lines written before the error repeat
+    fn write_lines(&self, lines: String) -> eyre::Result<()> {
+        match &mut *self.out.lock().unwrap() {
+            JsonlOutput::Stdout(stdout) => {
+                stdout.write_all(lines.as_bytes())?;
+                stdout.flush()?;
+            }
+            JsonlOutput::File(file) => {
+                let len = file.metadata()?.len();
+                let res = file.write_all(lines.as_bytes()).and_then(|_| file.flush());
+                if res.is_err() {
+                    let _ = file.set_len(len);
+                }
+                res?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PoolDataSink for JsonlSink {
+    fn name(&self) -> &'static str {
+        "jsonl"
+    }
+
+    async fn insert(&self, values: &[PoolData]) -> eyre::Result<()> {
+        let mut lines = String::new();
+        for value in values {
+            let (table, fields) = text_fields(value);
+            lines += &serde_json::to_string(&JsonLine { table, fields })?;
+            lines.push('\n');
+        }
+
+        self.write_lines(lines)
+    }
+
+    async fn retract(&self, from_block: u64, pools: &[Address]) -> eyre::Result<()> {
+        let line = JsonLine {
+            table: "retract",
+            fields: retract_fields(from_block, pools),
+        };
+        self.write_lines(serde_json::to_string(&line)? + "\n")
+    }
+}
+
+/// appends the values of each table to `<table>.csv`, with a header when the file is created
+///
+/// a retraction appends a row with its block for each pool to `retractions.csv`, every row of
+/// the pool from the block that was written before it should be dropped by the consumer
+pub struct CsvSink {
+    dir: PathBuf,
+    files: Mutex<HashMap<&'static str, File>>,
+}
+
+impl CsvSink {
+    pub fn new(dir: PathBuf) -> eyre::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        info!(target: "uniV3::db", "writing csv files to {}", dir.display());
+
+        Ok(Self {
+            dir,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn write_rows(&self, rows: Vec<(&'static str, TextFields)>) -> eyre::Result<()> {
+        let mut tables: HashMap<&'static str, Vec<TextFields>> = HashMap::new();
+        for (table, fields) in rows {
+            tables.entry(table).or_default().push(fields);
+        }
+
+        let mut files = self.files.lock().unwrap();
+        let mut written = Vec::new();
+        let res = tables.into_iter().try_for_each(|(table, rows)| {
+            let file = match files.entry(table) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    e.insert(open_csv(&self.dir.join(format!("{table}.csv")), &rows[0])?)
+                }
+            };
+
+            written.push((table, file.metadata()?.len()));
+            let lines = rows
+                .iter()
+                .map(|fields| csv_line(fields.iter().map(|(_, value)| csv_cell(value))))
+                .collect::<String>();
+            file.write_all(lines.as_bytes())?;
+
+            Ok::<_, eyre::ErrReport>(())
+        });
+
+        // the batch is retried whole, so the rows it already wrote would be duplicated
+        if res.is_err() {
+            for (table, len) in written {
+                if let Some(file) = files.get_mut(table) {
+                    let _ = file.set_len(len);
+                }
+            }
+        }
+
+        res
+    }
+}
+
+#[async_trait]
+impl PoolDataSink for CsvSink {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    async fn insert(&self, values: &[PoolData]) -> eyre::Result<()> {
+        self.write_rows(values.iter().map(text_fields).collect())
+    }
+
+    async fn retract(&self, from_block: u64, pools: &[Address]) -> eyre::Result<()> {
+        self.write_rows(
+            pools
+                .iter()
+                .map(|pool| {
+                    (
+                        "retractions",
+                        vec![
+                            ("from_block", from_block.into()),
+                            ("pool_address", hex(pool)),
+                        ],
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    async fn finish(&self) -> eyre::Result<()> {
+        for file in self.files.lock().unwrap().values_mut() {
+            file.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// opens the file for appending, writing the header if it's new
+fn open_csv(path: &Path, fields: &[(&'static str, Value)]) -> eyre::Result<File> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        file.write_all(csv_line(fields.iter().map(|(name, _)| name.to_string())).as_bytes())?;
+    }
+
+    Ok(file)
+}
+
+fn csv_line(cells: impl Iterator<Item = String>) -> String {
+    let mut line = cells.collect::<Vec<_>>().join(",");
+    line.push('\n');
+    line
+}
+
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) if s.contains([',', '"', '\n']) => {
+            format!("\"{}\"", s.replace('"', "\"\""))
+        }
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// a value as a json object with its fields in column order
+pub(super) struct JsonLine {
+    pub table: &'static str,
+    pub fields: TextFields,
+}
+
+impl Serialize for JsonLine {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.len() + 1))?;
+        map.serialize_entry("type", self.table)?;
+        for (name, value) in &self.fields {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+/// fields as a json object, in their column order
+pub(crate) struct JsonFields<'a>(pub &'a [(&'static str, Value)]);
+
+impl Serialize for JsonFields<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in self.0 {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+pub(super) fn text_fields(value: &PoolData) -> (&'static str, TextFields) {
+    match value {
+        PoolData::TickInfo(val) => (PoolTickInfo::TABLE, val.fields()),
+        PoolData::Slot0(val) => (PoolSlot0::TABLE, val.fields()),
+        PoolData::Trade(val) => (PoolTrade::TABLE, val.fields()),
+        PoolData::Candle(val) => (PoolCandle::TABLE, val.fields()),
+    }
+}
+
+/// the values of a row in the order of its `Row::COLUMN_NAMES`, with the 128 and 256 bit
+/// integers as decimal strings since `jq` and most csv readers would round them as floats
+///
+/// the sqlite sink stores the same values, so the column order is only kept in the `Row` structs
+pub(crate) trait TextRow: Row {
+    const TABLE: &'static str;
+
+    fn values(&self) -> Vec<Value>;
+
+    fn fields(&self) -> TextFields {
+        let values = self.values();
+        debug_assert_eq!(values.len(), Self::COLUMN_NAMES.len(), "{}", Self::TABLE);
+        Self::COLUMN_NAMES.iter().copied().zip(values).collect()
+    }
+}
+
+/// the fields of a retraction of the pools' values from `from_block`
+pub(crate) fn retract_fields(from_block: u64, pools: &[Address]) -> TextFields {
+    vec![
+        ("from_block", from_block.into()),
+        ("pools", Value::Array(pools.iter().map(hex).collect())),
+    ]
+}
+
+/// lowercase hex, as the addresses and hashes are stored in clickhouse
+pub(crate) fn hex_string<T: Debug>(value: T) -> String {
+    format!("{:?}", value).to_lowercase()
+}
+
+pub(crate) fn hex<T: Debug>(value: T) -> Value {
+    Value::String(hex_string(value))
+}
+
+pub(crate) fn decimal<T: ToString>(value: T) -> Value {
+    Value::String(value.to_string())
+}
+
+impl TextRow for PoolTickInfo {
+    const TABLE: &'static str = "tick_info";
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.block_number.into(),
+            hex(self.pool_address),
+            hex(self.tx_hash),
+            self.tx_index.into(),
+            self.tick.into(),
+            self.tick_spacing.into(),
+            decimal(self.liquidity_gross),
+            decimal(self.liquidity_net),
+            decimal(self.fee_growth_outside_0_x128),
+            decimal(self.fee_growth_outside_1_x128),
+            self.tick_cumulative_outside.into(),
+            decimal(self.seconds_per_liquidity_outside_x128),
+            self.seconds_outside.into(),
+            self.initialized.into(),
+        ]
+    }
+}
+
+impl TextRow for PoolSlot0 {
+    const TABLE: &'static str = "slot0";
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.block_number.into(),
+            hex(self.pool_address),
+            hex(self.token0),
+            self.token0_decimals.into(),
+            hex(self.token1),
+            self.token1_decimals.into(),
+            hex(self.tx_hash),
+            self.tx_index.into(),
+            self.tick.into(),
+            decimal(self.sqrt_price_x96),
+            self.calculated_price.into(),
+            self.observation_index.into(),
+            self.observation_cardinality.into(),
+            self.observation_cardinality_next.into(),
+            self.fee_protocol.into(),
+            self.unlocked.into(),
+        ]
+    }
+}
+
+impl TextRow for PoolTrade {
+    const TABLE: &'static str = "trades";
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            self.block_number.into(),
+            hex(self.tx_hash),
+            hex(self.pool_address),
+            hex(self.token_in),
+            self.token_in_decimals.into(),
+            decimal(self.token_in_amount),
+            hex(self.token_out),
+            self.token_out_decimals.into(),
+            decimal(self.token_out_amount),
+            self.calculated_price.into(),
+        ]
+    }
+}
+
+impl TextRow for PoolCandle {
+    const TABLE: &'static str = "candles";
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            hex(self.pool_address),
+            self.interval.clone().into(),
+            self.bucket_start.into(),
+            self.start_block.into(),
+            self.end_block.into(),
+            hex(self.token0),
+            hex(self.token1),
+            self.open.into(),
+            self.high.into(),
+            self.low.into(),
+            self.close.into(),
+            self.volume_token0.into(),
+            self.volume_token1.into(),
+            self.trade_count.into(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pools::types::trade, utils::temp_path};
+
+    #[tokio::test]
+    async fn test_jsonl_lines() {
+        let path = temp_path("sink.jsonl");
+
+        let sink = JsonlSink::new(&path).unwrap();
+        sink.insert(&[trade(10, 5), trade(11, 7)]).await.unwrap();
+        sink.retract(11, &[Address::with_last_byte(1)])
+            .await
+            .unwrap();
+
+        let lines = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["type"], "trades");
+        assert_eq!(lines[0]["block_number"], 10);
+        assert_eq!(lines[1]["token_out_amount"], "-7");
+        assert_eq!(
+            lines[0]["pool_address"],
+            "0x0000000000000000000000000000000000000001"
+        );
+        assert_eq!(
+            lines[2],
+            serde_json::json!({
+                "type": "retract",
+                "from_block": 11,
+                "pools": ["0x0000000000000000000000000000000000000001"]
+            })
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_csv_files() {
+        let dir = temp_path("csv");
+
+        let sink = CsvSink::new(dir.clone()).unwrap();
+        sink.insert(&[trade(10, 5)]).await.unwrap();
+        sink.insert(&[trade(11, 7)]).await.unwrap();
+        sink.retract(
+            11,
+            &[Address::with_last_byte(1), Address::with_last_byte(2)],
+        )
+        .await
+        .unwrap();
+
+        let trades = std::fs::read_to_string(dir.join("trades.csv")).unwrap();
+        let lines = trades.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("block_number,tx_hash,pool_address"));
+        assert!(lines[2].starts_with("11,"));
+        assert!(lines[2].contains(",-7,"));
+
+        let retractions = std::fs::read_to_string(dir.join("retractions.csv")).unwrap();
+        assert_eq!(
+            retractions,
+            "from_block,pool_address\n\
+             11,0x0000000000000000000000000000000000000001\n\
+             11,0x0000000000000000000000000000000000000002\n"
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
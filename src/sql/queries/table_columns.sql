@@ -0,0 +1 @@
+SELECT name, type AS column_type FROM system.columns WHERE database = ? AND table = ? ORDER BY position
\ No newline at end of file
@@ -0,0 +1,81 @@
+CREATE TABLE IF NOT EXISTS uni_v3_tick_info
+(
+    `block_number` INTEGER NOT NULL,
+    `pool_address` TEXT NOT NULL,
+    `tx_hash` TEXT NOT NULL,
+    `tx_index` INTEGER NOT NULL,
+    `tick` INTEGER NOT NULL,
+    `tick_spacing` INTEGER NOT NULL,
+    `liquidity_gross` TEXT NOT NULL,
+    `liquidity_net` TEXT NOT NULL,
+    `fee_growth_outside_0_x128` TEXT NOT NULL,
+    `fee_growth_outside_1_x128` TEXT NOT NULL,
+    `tick_cumulative_outside` INTEGER NOT NULL,
+    `seconds_per_liquidity_outside_x128` TEXT NOT NULL,
+    `seconds_outside` INTEGER NOT NULL,
+    `initialized` INTEGER NOT NULL,
+    `last_updated` INTEGER NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (`block_number`, `pool_address`, `tx_hash`, `tick`)
+);
+CREATE INDEX IF NOT EXISTS uni_v3_tick_info_pool_block ON uni_v3_tick_info (`pool_address`, `block_number`, `tx_index`);
+
+CREATE TABLE IF NOT EXISTS uni_v3_slot0
+(
+    `block_number` INTEGER NOT NULL,
+    `pool_address` TEXT NOT NULL,
+    `token0` TEXT NOT NULL,
+    `token0_decimals` INTEGER NOT NULL,
+    `token1` TEXT NOT NULL,
+    `token1_decimals` INTEGER NOT NULL,
+    `tx_hash` TEXT NOT NULL,
+    `tx_index` INTEGER NOT NULL,
+    `tick` INTEGER NOT NULL,
+    `sqrt_price_x96` TEXT NOT NULL,
+    `calculated_price` REAL NOT NULL,
+    `observation_index` INTEGER NOT NULL,
+    `observation_cardinality` INTEGER NOT NULL,
+    `observation_cardinality_next` INTEGER NOT NULL,
+    `fee_protocol` INTEGER NOT NULL,
+    `unlocked` INTEGER NOT NULL,
+    `last_updated` INTEGER NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (`block_number`, `pool_address`, `tx_hash`, `tick`)
+);
+CREATE INDEX IF NOT EXISTS uni_v3_slot0_pool_block ON uni_v3_slot0 (`pool_address`, `block_number`, `tx_index`);
+
+CREATE TABLE IF NOT EXISTS uni_v3_trades
+(
+    `block_number` INTEGER NOT NULL,
+    `tx_hash` TEXT NOT NULL,
+    `pool_address` TEXT NOT NULL,
+    `token_in` TEXT NOT NULL,
+    `token_in_decimals` INTEGER NOT NULL,
+    `token_in_amount` TEXT NOT NULL,
+    `token_out` TEXT NOT NULL,
+    `token_out_decimals` INTEGER NOT NULL,
+    `token_out_amount` TEXT NOT NULL,
+    `calculated_price` REAL NOT NULL,
+    `last_updated` INTEGER NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (`block_number`, `pool_address`, `tx_hash`)
+);
+CREATE INDEX IF NOT EXISTS uni_v3_trades_pool_block ON uni_v3_trades (`pool_address`, `block_number`);
+
+CREATE TABLE IF NOT EXISTS uni_v3_candles
+(
+    `pool_address` TEXT NOT NULL,
+    `interval` TEXT NOT NULL,
+    `bucket_start` INTEGER NOT NULL,
+    `start_block` INTEGER NOT NULL,
+    `end_block` INTEGER NOT NULL,
+    `token0` TEXT NOT NULL,
+    `token1` TEXT NOT NULL,
+    `open` REAL NOT NULL,
+    `high` REAL NOT NULL,
+    `low` REAL NOT NULL,
+    `close` REAL NOT NULL,
+    `volume_token0` REAL NOT NULL,
+    `volume_token1` REAL NOT NULL,
+    `trade_count` INTEGER NOT NULL,
+    `last_updated` INTEGER NOT NULL DEFAULT (unixepoch()),
+    PRIMARY KEY (`pool_address`, `interval`, `bucket_start`)
+);
+CREATE INDEX IF NOT EXISTS uni_v3_candles_pool_start_block ON uni_v3_candles (`pool_address`, `start_block`);
@@ -0,0 +1,21 @@
+CREATE TABLE eth_analytics.uni_v3_candles ON CLUSTER eth_cluster0
+(
+    `pool_address` String,
+    `interval` String,
+    `bucket_start` UInt64,
+    `start_block` UInt64,
+    `end_block` UInt64,
+    `token0` String,
+    `token1` String,
+    `open` Float64,
+    `high` Float64,
+    `low` Float64,
+    `close` Float64,
+    `volume_token0` Float64,
+    `volume_token1` Float64,
+    `trade_count` UInt64,
+    `last_updated` UInt64 Default now()
+)
+ENGINE = ReplicatedReplacingMergeTree('/clickhouse/eth_cluster0/tables/all/eth_analytics/uni_v3_candles', '{replica}', `last_updated`)
+PRIMARY KEY (`pool_address`, `interval`)
+ORDER BY (`pool_address`, `interval`, `bucket_start`)
\ No newline at end of file
@@ -1,7 +1,8 @@
 use alloy_primitives::{Address, U160, U256};
 use malachite::Natural;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TokenInfo {
     pub address: Address,
     pub decimals: u8,
@@ -13,6 +14,19 @@ impl TokenInfo {
     }
 }
 
+/// a path in the temp dir that no other test uses, also across parallel test runs
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "uniV3_test_{}_{}_{name}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
 pub fn u160_to_natural(num: U160) -> Natural {
     Natural::from_limbs_asc(&num.into_limbs())
 }
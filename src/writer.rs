@@ -0,0 +1,719 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use alloy_primitives::Address;
+use futures::{future::join_all, Future, FutureExt};
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{error, info};
+
+use crate::{
+    backlog::PoolDataReceiver,
+    candles::CandleAggregator,
+    checkpoint::CheckpointTracker,
+    metrics,
+    pools::types::{PoolData, PoolUpdate},
+    retry::{with_delay, DeadLetter, FailedStage, RetryPolicy},
+    sinks::PoolDataSink,
+};
+
+/// a write to the sinks
+#[derive(Clone)]
+enum WriteOp {
+    Insert(Arc<Vec<PoolData>>),
+    /// deletes the values of every block from the block, run after any insert in flight
+    Retract(u64),
+    /// run once everything was written
+    Finish,
+}
+
+/// the sinks that failed the write and the first of their errors
+type WriteResult = Result<(), (Vec<Arc<dyn PoolDataSink>>, eyre::ErrReport)>;
+
+/// buffers the values sent by the fetchers and writes them to every sink in batches of
+/// `insert_size`, in the order the blocks and reorgs were sent
+pub struct BufferedWriter {
+    pub sinks: Vec<Arc<dyn PoolDataSink>>,
+    /// the pools of the run, only their values are retracted
+    pub pools: Arc<Vec<Address>>,
+    pub rx: PoolDataReceiver,
+    pub candles: Option<CandleAggregator>,
+    pub checkpoint: Option<CheckpointTracker>,
+    fut: Option<Pin<Box<dyn Future<Output = WriteResult> + Send>>>,
+    pub queue: Vec<PoolData>,
+    pub inserting: Vec<PoolData>,
+    /// blocks whose values are all in `queue`
+    pub queued_blocks: Vec<u64>,
+    /// blocks whose values are all in `inserting`
+    pub inserting_blocks: Vec<u64>,
+    pub insert_size: usize,
+    pub retry: RetryPolicy,
+    /// failed attempts of the write in flight
+    pub attempts: u32,
+    /// a reorg that has not been retracted yet
+    pub retract_from: Option<u64>,
+    /// the write in flight
+    writing: Option<WriteOp>,
+    /// inserts whatever is queued on each tick, even if less than `insert_size`
+    pub flush_interval: Option<Interval>,
+    /// the last block the sinks were told every block up to was written
+    completed_block: Option<u64>,
+    finished: bool,
+}
+
+impl BufferedWriter {
+    pub fn new(
+        sinks: Vec<Arc<dyn PoolDataSink>>,
+        pools: Vec<Address>,
+        rx: PoolDataReceiver,
+        insert_size: usize,
+        candles: Option<CandleAggregator>,
+        checkpoint: Option<CheckpointTracker>,
+        retry: RetryPolicy,
+    ) -> Self {
+        info!(target: "uniV3::db", "writing to sinks {:?}", sinks.iter().map(|sink| sink.name()).collect::<Vec<_>>());
+        Self {
+            sinks,
+            pools: Arc::new(pools),
+            rx,
+            candles,
+            checkpoint,
+            fut: None,
+            queue: Vec::new(),
+            inserting: Vec::new(),
+            queued_blocks: Vec::new(),
+            inserting_blocks: Vec::new(),
+            insert_size,
+            retry,
+            attempts: 0,
+            retract_from: None,
+            writing: None,
+            flush_interval: None,
+            completed_block: None,
+            finished: false,
+        }
+    }
+
+    pub fn with_flush_interval(mut self, period: Duration) -> Self {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    fn start_write(&mut self, sinks: Vec<Arc<dyn PoolDataSink>>, op: WriteOp, delay: Duration) {
+        self.writing = Some(op.clone());
+        self.fut = Some(Box::pin(with_delay(
+            delay,
+            Self::write(sinks, op, self.pools.clone()),
+        )));
+    }
+
+    /// runs the write on every sink at once, so a slow sink only holds back the next batch
+    async fn write(
+        sinks: Vec<Arc<dyn PoolDataSink>>,
+        op: WriteOp,
+        pools: Arc<Vec<Address>>,
+    ) -> WriteResult {
+        let results = join_all(sinks.iter().map(|sink| {
+            let (op, pools) = (&op, &pools);
+            async move {
+                let started = Instant::now();
+                let res = match op {
+                    WriteOp::Insert(values) => sink.insert(values).await,
+                    WriteOp::Retract(from_block) => sink.retract(*from_block, pools).await,
+                    WriteOp::Finish => sink.finish().await,
+                };
+
+                (res, started.elapsed())
+            }
+        }))
+        .await;
+
+        let mut failed = Vec::new();
+        let mut error = None;
+        for (sink, (res, elapsed)) in sinks.into_iter().zip(results) {
+            match res {
+                Ok(()) => {
+                    if matches!(op, WriteOp::Insert(_)) {
+                        metrics::record_insert(sink.name(), elapsed);
+                    }
+                }
+                Err(e) => {
+                    if matches!(op, WriteOp::Insert(_)) {
+                        metrics::record_insert_failure(sink.name());
+                    }
+                    error!(target: "uniV3::db", "{} sink failed - {:?}", sink.name(), e);
+                    error.get_or_insert(e);
+                    failed.push(sink);
+                }
+            }
+        }
+
+        match error {
+            Some(e) => Err((failed, e)),
+            None => Ok(()),
+        }
+    }
+
+    fn on_written(&mut self) {
+        self.attempts = 0;
+
+        match self.writing.take() {
+            Some(WriteOp::Retract(from_block)) => {
+                info!(target: "uniV3::db", "retracted values from block {from_block}");
+            }
+            Some(WriteOp::Finish) => self.finished = true,
+            Some(WriteOp::Insert(_)) | None => {
+                info!(target: "uniV3::db", "inserted {} values", self.inserting.len());
+                self.rx.written(&self.inserting);
+                self.inserting.clear();
+
+                let blocks = std::mem::take(&mut self.inserting_blocks);
+                if let Some(checkpoint) = self.checkpoint.as_mut() {
+                    if let Err(e) = checkpoint.confirm(blocks) {
+                        error!(target: "uniV3::checkpoint", "failed to save checkpoint - {:?}", e);
+                    }
+                }
+                self.on_completed();
+            }
+        }
+    }
+
+    /// retries the write on the sinks that failed with a backoff, giving up on it once it's out
+    /// of attempts
+    fn on_write_failed(&mut self, failed: Vec<Arc<dyn PoolDataSink>>, e: eyre::ErrReport) {
+        self.attempts += 1;
+        let Some(op) = self.writing.clone() else {
+            return;
+        };
+
+        if self.retry.should_retry(self.attempts) {
+            let delay = self.retry.delay(self.attempts);
+            let stage = match &op {
+                WriteOp::Insert(_) => {
+                    error!(target: "uniV3::db", "error inserting (attempt {}/{}), RETRYING in {:?} - {:?}", self.attempts, self.retry.max_attempts, delay, e);
+                    self.rx.writes.retried();
+                    Some(FailedStage::Insert)
+                }
+                WriteOp::Retract(from_block) => {
+                    error!(target: "uniV3::db", "error retracting values from block {from_block} (attempt {}/{}), RETRYING in {:?} - {:?}", self.attempts, self.retry.max_attempts, delay, e);
+                    Some(FailedStage::Retract)
+                }
+                WriteOp::Finish => {
+                    error!(target: "uniV3::db", "error finishing the sinks (attempt {}/{}), RETRYING in {:?} - {:?}", self.attempts, self.retry.max_attempts, delay, e);
+                    None
+                }
+            };
+            if let Some(stage) = stage {
+                metrics::record_retry(stage);
+            }
+
+            self.start_write(failed, op, delay);
+            return;
+        }
+
+        // the values are dropped and the run goes on, but the blocks are never checkpointed
+        match op {
+            WriteOp::Insert(_) => {
+                let blocks = std::mem::take(&mut self.inserting_blocks);
+                self.rx.writes.gave_up(blocks.len());
+                self.retry.record(DeadLetter::new(
+                    FailedStage::Insert,
+                    blocks.clone(),
+                    self.attempts,
+                    &e,
+                ));
+                self.fail_blocks(blocks);
+            }
+            WriteOp::Retract(from_block) => self.retry.record(DeadLetter::new(
+                FailedStage::Retract,
+                vec![from_block],
+                self.attempts,
+                &e,
+            )),
+            WriteOp::Finish => {
+                error!(target: "uniV3::db", "giving up on finishing the sinks - {:?}", e)
+            }
+        }
+        self.on_written();
+    }
+
+    fn fail_blocks(&mut self, blocks: Vec<u64>) {
+        if let Some(checkpoint) = self.checkpoint.as_mut() {
+            if let Err(e) = checkpoint.fail(blocks) {
+                error!(target: "uniV3::checkpoint", "failed to save checkpoint - {:?}", e);
+            }
+        }
+        self.on_completed();
+    }
+
+    /// tells the sinks once the contiguous run of written blocks moved
+    fn on_completed(&mut self) {
+        let Some(block) = self
+            .checkpoint
+            .as_ref()
+            .and_then(CheckpointTracker::written_block)
+        else {
+            return;
+        };
+        if self.completed_block == Some(block) {
+            return;
+        }
+
+        self.completed_block = Some(block);
+        for sink in &self.sinks {
+            sink.on_completed(block);
+        }
+    }
+
+    /// hands the values to the sinks that stream them before they're batched
+    fn on_block(&self, values: &[PoolData]) {
+        if values.is_empty() {
+            return;
+        }
+
+        for sink in &self.sinks {
+            sink.on_block(values);
+        }
+    }
+
+    /// drops everything still buffered from `from_block` and schedules the retraction of what
+    /// was already written
+    fn on_reorg(&mut self, from_block: u64) {
+        for sink in &self.sinks {
+            sink.on_reorg(from_block, &self.pools);
+        }
+
+        let (dropped, queue) = std::mem::take(&mut self.queue)
+            .into_iter()
+            .partition::<Vec<_>, _>(|val| val.block_number() >= from_block);
+        self.rx.written(&dropped);
+        self.queue = queue;
+        self.queued_blocks.retain(|block| *block < from_block);
+        self.inserting_blocks.retain(|block| *block < from_block);
+
+        if let Some(candles) = self.candles.as_mut() {
+            candles.retract(from_block);
+        }
+        if let Some(checkpoint) = self.checkpoint.as_mut() {
+            if let Err(e) = checkpoint.rewind(from_block) {
+                error!(target: "uniV3::checkpoint", "failed to save checkpoint - {:?}", e);
+            }
+        }
+        self.completed_block = None;
+
+        self.retract_from = Some(
+            self.retract_from
+                .map_or(from_block, |block| block.min(from_block)),
+        );
+    }
+}
+
+impl Future for BufferedWriter {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        let mut is_finished = false;
+
+        if let Poll::Ready(inc) = this.rx.poll_recv(cx) {
+            if let Some(update) = inc {
+                match update {
+                    PoolUpdate::Block(block) => {
+                        if let Some(candles) = this.candles.as_mut() {
+                            let closed = candles.on_block(&block);
+                            this.rx.added(&closed);
+                            this.on_block(&closed);
+                            this.queue.extend(closed);
+                        }
+                        this.on_block(&block.data);
+                        this.queue.extend(block.data);
+                        if block.failed {
+                            this.fail_blocks(vec![block.block_number]);
+                        } else {
+                            this.queued_blocks.push(block.block_number);
+                        }
+                    }
+                    PoolUpdate::Reorg { from_block } => this.on_reorg(from_block),
+                }
+            } else {
+                if let Some(candles) = this.candles.as_mut() {
+                    candles.finish();
+                }
+                is_finished = true;
+            }
+        }
+
+        let flush_due = this
+            .flush_interval
+            .as_mut()
+            .is_some_and(|interval| interval.poll_tick(cx).is_ready());
+
+        let fut = this.fut.take();
+        if let Some(mut f) = fut {
+            if let Poll::Ready(val) = f.poll_unpin(cx) {
+                match val {
+                    Ok(()) => this.on_written(),
+                    Err((failed, e)) => this.on_write_failed(failed, e),
+                }
+            } else {
+                this.fut = Some(f)
+            }
+        } else if let Some(from_block) = this.retract_from.take() {
+            this.start_write(
+                this.sinks.clone(),
+                WriteOp::Retract(from_block),
+                Duration::ZERO,
+            );
+        } else if (!this.queue.is_empty() || !this.queued_blocks.is_empty())
+            && (this.queue.len() >= this.insert_size
+                || is_finished
+                || flush_due
+                // the handler waits for the backlog to drain, so waiting for a full batch
+                // would never end
+                || this.rx.backlog.is_full())
+        {
+            this.inserting = this.queue.drain(..).collect::<Vec<_>>();
+            this.inserting_blocks = std::mem::take(&mut this.queued_blocks);
+
+            let values = Arc::new(this.inserting.clone());
+            this.start_write(this.sinks.clone(), WriteOp::Insert(values), Duration::ZERO);
+        } else if is_finished {
+            if this.finished {
+                if let Some(checkpoint) = this.checkpoint.as_ref() {
+                    checkpoint.log_completed();
+                }
+                info!(target: "uniV3::db", "finished writing to every sink");
+                return Poll::Ready(());
+            }
+
+            this.start_write(this.sinks.clone(), WriteOp::Finish, Duration::ZERO);
+        }
+        metrics::record_queue_depth(
+            this.queue.len() + this.inserting.len(),
+            this.queued_blocks.len() + this.inserting_blocks.len(),
+        );
+
+        cx.waker().wake_by_ref();
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use async_trait::async_trait;
+    use tokio::sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Semaphore,
+    };
+
+    use super::*;
+    use crate::{
+        backlog::{pool_data_channel, PoolDataSender, WriteBacklog},
+        pools::types::{trade, PoolBlockData},
+        sinks::{ChannelSink, SinkEvent},
+        utils::temp_path,
+    };
+
+    /// records its writes like `ChannelSink`, failing the first `failures` inserts and holding
+    /// each insert until `gate` has a permit
+    struct MockSink {
+        events: UnboundedSender<SinkEvent>,
+        /// the values handed to `on_block`
+        blocks: std::sync::Mutex<Vec<PoolData>>,
+        failures: AtomicU32,
+        gate: Semaphore,
+        inserts: AtomicU32,
+        finishes: AtomicU32,
+    }
+
+    impl MockSink {
+        fn new(failures: u32, permits: usize) -> (Arc<Self>, UnboundedReceiver<SinkEvent>) {
+            let (events, rx) = unbounded_channel();
+            let sink = Self {
+                events,
+                blocks: Default::default(),
+                failures: AtomicU32::new(failures),
+                gate: Semaphore::new(permits),
+                inserts: AtomicU32::new(0),
+                finishes: AtomicU32::new(0),
+            };
+
+            (Arc::new(sink), rx)
+        }
+    }
+
+    #[async_trait]
+    impl PoolDataSink for MockSink {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        async fn insert(&self, values: &[PoolData]) -> eyre::Result<()> {
+            self.gate.acquire().await?.forget();
+            self.inserts.fetch_add(1, Ordering::Relaxed);
+            if self
+                .failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err(eyre::ErrReport::msg("insert failed"));
+            }
+
+            Ok(self.events.send(SinkEvent::Insert(values.to_vec()))?)
+        }
+
+        async fn retract(&self, from_block: u64, pools: &[Address]) -> eyre::Result<()> {
+            Ok(self.events.send(SinkEvent::Retract {
+                from_block,
+                pools: pools.to_vec(),
+            })?)
+        }
+
+        async fn finish(&self) -> eyre::Result<()> {
+            self.finishes.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn on_block(&self, values: &[PoolData]) {
+            self.blocks.lock().unwrap().extend_from_slice(values);
+        }
+    }
+
+    fn writer(
+        sinks: Vec<Arc<dyn PoolDataSink>>,
+        insert_size: usize,
+        max_attempts: u32,
+    ) -> (PoolDataSender, BufferedWriter) {
+        let (tx, rx) = pool_data_channel(WriteBacklog::new(usize::MAX, usize::MAX));
+        let writer = BufferedWriter::new(
+            sinks,
+            vec![Address::with_last_byte(1)],
+            rx,
+            insert_size,
+            None,
+            None,
+            retry(max_attempts),
+        );
+
+        (tx, writer)
+    }
+
+    fn send_block(tx: &PoolDataSender, block_number: u64, amount: i64) {
+        tx.send(PoolBlockData::new(block_number, 0, vec![trade(block_number, amount)]).into())
+            .unwrap();
+    }
+
+    fn received(events: &mut UnboundedReceiver<SinkEvent>) -> Vec<SinkEvent> {
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        received
+    }
+
+    fn retry(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            retry_delay_ms: 0,
+            max_retry_delay_ms: 0,
+            dead_letters: temp_path("dead_letters.jsonl"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reorg_retracts_before_reinserting() {
+        let (events_tx, mut events) = unbounded_channel();
+        let (tx, rx) = pool_data_channel(WriteBacklog::new(usize::MAX, usize::MAX));
+        let pool = Address::with_last_byte(1);
+        let writer = BufferedWriter::new(
+            vec![Arc::new(ChannelSink::new(events_tx))],
+            vec![pool],
+            rx,
+            2,
+            None,
+            None,
+            retry(1),
+        );
+
+        for (block_number, amount) in [(10, 1), (11, 2), (12, 3)] {
+            tx.send(PoolBlockData::new(block_number, 0, vec![trade(block_number, amount)]).into())
+                .unwrap();
+        }
+        tx.send(PoolUpdate::Reorg { from_block: 11 }).unwrap();
+        tx.send(PoolBlockData::new(11, 0, vec![trade(11, 4)]).into())
+            .unwrap();
+        drop(tx);
+        writer.await;
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        // block 12 was still queued, so it's dropped without being written
+        assert_eq!(
+            received,
+            vec![
+                SinkEvent::Insert(vec![trade(10, 1), trade(11, 2)]),
+                SinkEvent::Retract {
+                    from_block: 11,
+                    pools: vec![pool]
+                },
+                SinkEvent::Insert(vec![trade(11, 4)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_full_backlog_flushes_a_partial_batch() {
+        let (events_tx, mut events) = unbounded_channel();
+        let (tx, rx) = pool_data_channel(WriteBacklog::new(2, usize::MAX));
+        let writer = BufferedWriter::new(
+            vec![Arc::new(ChannelSink::new(events_tx))],
+            vec![Address::with_last_byte(1)],
+            rx,
+            100,
+            None,
+            None,
+            retry(1),
+        );
+        let writer = tokio::spawn(writer);
+
+        // the sender stays open, so only the full backlog can trigger the insert
+        tx.send(PoolBlockData::new(10, 0, vec![trade(10, 1), trade(10, 2)]).into())
+            .unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap();
+        assert_eq!(
+            event,
+            Some(SinkEvent::Insert(vec![trade(10, 1), trade(10, 2)]))
+        );
+        assert!(!tx.backlog.is_full());
+
+        drop(tx);
+        writer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hands_each_block_to_the_sinks_before_inserting() {
+        // no insert can complete until the gate gets permits
+        let (sink, mut events) = MockSink::new(0, 0);
+        let (tx, writer) = writer(vec![sink.clone()], 100, 1);
+        let writer = tokio::spawn(writer);
+
+        send_block(&tx, 10, 1);
+        send_block(&tx, 11, 2);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sink.blocks.lock().unwrap().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            *sink.blocks.lock().unwrap(),
+            vec![trade(10, 1), trade(11, 2)]
+        );
+        assert!(received(&mut events).is_empty());
+
+        sink.gate.add_permits(Semaphore::MAX_PERMITS);
+        drop(tx);
+        writer.await.unwrap();
+        assert_eq!(
+            received(&mut events),
+            vec![SinkEvent::Insert(vec![trade(10, 1), trade(11, 2)])]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inserts_in_batches_of_insert_size() {
+        let (sink, mut events) = MockSink::new(0, Semaphore::MAX_PERMITS);
+        let (tx, writer) = writer(vec![sink.clone()], 2, 1);
+
+        for block_number in [10, 11, 12] {
+            send_block(&tx, block_number, 1);
+        }
+        drop(tx);
+        writer.await;
+
+        // the last batch is short, it's only flushed once the senders are gone
+        assert_eq!(
+            received(&mut events),
+            vec![
+                SinkEvent::Insert(vec![trade(10, 1), trade(11, 1)]),
+                SinkEvent::Insert(vec![trade(12, 1)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retries_only_the_failed_sink() {
+        let (failing, mut failing_events) = MockSink::new(2, Semaphore::MAX_PERMITS);
+        let (working, mut working_events) = MockSink::new(0, Semaphore::MAX_PERMITS);
+        let (tx, writer) = writer(vec![failing.clone(), working.clone()], 1, 3);
+
+        send_block(&tx, 10, 1);
+        let writes = tx.writes.clone();
+        drop(tx);
+        writer.await;
+
+        assert_eq!(writes.snapshot(), [2, 0]);
+        assert_eq!(failing.inserts.load(Ordering::Relaxed), 3);
+        assert_eq!(working.inserts.load(Ordering::Relaxed), 1);
+        let inserted = vec![SinkEvent::Insert(vec![trade(10, 1)])];
+        assert_eq!(received(&mut failing_events), inserted);
+        assert_eq!(received(&mut working_events), inserted);
+    }
+
+    #[tokio::test]
+    async fn test_retract_waits_for_the_insert_in_flight() {
+        let (sink, mut events) = MockSink::new(0, 0);
+        let (tx, writer) = writer(vec![sink.clone()], 1, 1);
+        let writer = tokio::spawn(writer);
+
+        send_block(&tx, 10, 1);
+        tx.send(PoolUpdate::Reorg { from_block: 10 }).unwrap();
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        // the insert is held by the sink, so the retraction can't have started
+        assert!(received(&mut events).is_empty());
+
+        sink.gate.add_permits(1);
+        drop(tx);
+        writer.await.unwrap();
+
+        assert_eq!(
+            received(&mut events),
+            vec![
+                SinkEvent::Insert(vec![trade(10, 1)]),
+                SinkEvent::Retract {
+                    from_block: 10,
+                    pools: vec![Address::with_last_byte(1)]
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_finishes_the_sinks_once() {
+        let (sink, _events) = MockSink::new(0, Semaphore::MAX_PERMITS);
+        let (tx, writer) = writer(vec![sink.clone()], 100, 1);
+
+        send_block(&tx, 10, 1);
+        drop(tx);
+        writer.await;
+
+        assert_eq!(sink.inserts.load(Ordering::Relaxed), 1);
+        assert_eq!(sink.finishes.load(Ordering::Relaxed), 1);
+    }
+}